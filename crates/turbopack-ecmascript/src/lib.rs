@@ -91,6 +91,18 @@ pub struct EcmascriptOptions {
     pub split_into_parts: bool,
     /// imports will import parts of modules
     pub import_parts: bool,
+    /// `export * from "..."` is kept as a runtime loop copying the bindings
+    /// of the referenced module instead of being expanded at compile time
+    pub star_reexport_shims: bool,
+    /// Namespaces the magic identifiers of unnamed default exports, to avoid
+    /// collisions with other tools using the same mangling
+    pub magic_identifier_prefix: Option<StringVc>,
+    /// The binding of a default exported expression is declared with `let`
+    /// instead of `const`, for modules which reassign it
+    pub reassignable_default_export: bool,
+    /// The empty statements left behind by removed import and export
+    /// declarations are removed from the output
+    pub remove_empty_statements: bool,
 }
 
 #[turbo_tasks::value(serialization = "auto_for_input")]
@@ -395,6 +407,7 @@ impl EcmascriptChunkItem for ModuleChunkItem {
             parsed,
             this.module.ident(),
             availability_info,
+            module.options.remove_empty_statements,
         ))
     }
 }
@@ -406,6 +419,7 @@ async fn gen_content(
     parsed: ParseResultVc,
     ident: AssetIdentVc,
     availability_info: Value<AvailabilityInfo>,
+    remove_empty_statements: bool,
 ) -> Result<EcmascriptChunkItemContentVc> {
     let AnalyzeEcmascriptModuleResult {
         references,
@@ -476,6 +490,9 @@ async fn gen_content(
             // we need to remove any shebang before bundling as it's only valid as the first
             // line in a js file (not in a chunk item wrapped in the runtime)
             remove_shebang(&mut program);
+            if remove_empty_statements {
+                references::esm::module_item::remove_empty_statements(&mut program);
+            }
         });
 
         let mut bytes: Vec<u8> = vec![];
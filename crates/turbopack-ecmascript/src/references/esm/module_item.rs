@@ -5,14 +5,22 @@ use swc_core::{
     common::DUMMY_SP,
     ecma::{
         ast::{
-            ClassDecl, Decl, DefaultDecl, ExportDecl, ExportDefaultDecl, ExportDefaultExpr, FnDecl,
-            Ident, ModuleDecl, ModuleItem, Stmt,
+            BindingIdent, ClassDecl, Decl, DefaultDecl, ExportDecl, ExportDefaultDecl,
+            ExportDefaultExpr, Expr, ExprStmt, FnDecl, Ident, Lit, Module, ModuleDecl, ModuleItem,
+            ParenExpr, Pat, Program, Stmt, VarDecl, VarDeclKind, VarDeclarator,
         },
         visit::AstParentKind,
     },
     quote,
 };
+use turbo_tasks::primitives::StringVc;
+use turbopack_core::{
+    asset::{Asset, AssetVc},
+    ident::AssetIdentVc,
+    issue::{analyze::AnalyzeIssue, IssueSeverity},
+};
 
+use super::EsmAssetReferenceVc;
 use crate::{
     chunk::EcmascriptChunkingContextVc,
     code_gen::{CodeGenerateable, CodeGenerateableVc, CodeGeneration, CodeGenerationVc},
@@ -27,13 +35,61 @@ use crate::{
 #[derive(Hash, Debug)]
 pub struct EsmModuleItem {
     pub path: AstPathVc,
+    /// The module containing the declaration, to report issues on
+    pub source: AssetVc,
+    /// The binding used for an unnamed default export, see
+    /// [default_export_name]
+    pub default_export_name: Option<String>,
+    /// Declares the binding of a default exported expression with `let`
+    /// instead of `const`, so it can be reassigned
+    pub reassignable_default_export: bool,
+    /// When set, an `export * from "..."` is replaced with a runtime loop
+    /// copying the bindings of the referenced module instead of being removed
+    pub star_reexport: Option<EsmAssetReferenceVc>,
 }
 
 #[turbo_tasks::value_impl]
 impl EsmModuleItemVc {
     #[turbo_tasks::function]
-    pub fn new(path: AstPathVc) -> Self {
-        Self::cell(EsmModuleItem { path })
+    pub fn new(path: AstPathVc, source: AssetVc) -> Self {
+        Self::cell(EsmModuleItem {
+            path,
+            source,
+            default_export_name: None,
+            reassignable_default_export: false,
+            star_reexport: None,
+        })
+    }
+
+    #[turbo_tasks::function]
+    pub fn new_default_export(
+        path: AstPathVc,
+        source: AssetVc,
+        default_export_name: String,
+        reassignable_default_export: bool,
+    ) -> Self {
+        Self::cell(EsmModuleItem {
+            path,
+            source,
+            default_export_name: Some(default_export_name),
+            reassignable_default_export,
+            star_reexport: None,
+        })
+    }
+
+    #[turbo_tasks::function]
+    pub fn new_star_reexport_shim(
+        path: AstPathVc,
+        source: AssetVc,
+        reference: EsmAssetReferenceVc,
+    ) -> Self {
+        Self::cell(EsmModuleItem {
+            path,
+            source,
+            default_export_name: None,
+            reassignable_default_export: false,
+            star_reexport: Some(reference),
+        })
     }
 }
 
@@ -51,63 +107,558 @@ impl CodeGenerateable for EsmModuleItem {
             matches!(path.last(), Some(AstParentKind::ModuleDecl(_))),
             "EsmModuleItem was created with a path that points to a unexpected ast node"
         );
+        let star_reexport_ident = if let Some(reference) = self.star_reexport {
+            reference.get_referenced_asset().await?.get_ident().await?
+        } else {
+            None
+        };
+        let default_export_ident = self
+            .default_export_name
+            .clone()
+            .unwrap_or_else(|| default_export_name(None));
+        let default_export_kind = if self.reassignable_default_export {
+            VarDeclKind::Let
+        } else {
+            VarDeclKind::Const
+        };
+        let source_ident = self.source.ident();
         visitors.push(
             create_visitor!(path, visit_mut_module_item(module_item: &mut ModuleItem) {
-                let item = replace(module_item, ModuleItem::Stmt(quote!(";" as Stmt)));
-                if let ModuleItem::ModuleDecl(module_decl) = item {
-                    match module_decl {
-                        ModuleDecl::ExportDefaultExpr(ExportDefaultExpr { box expr, .. }) => {
-                            let stmt = quote!("const $name = $expr;" as Stmt,
-                                name = Ident::new(magic_identifier::mangle("default export").into(), DUMMY_SP),
-                                expr: Expr = expr
-                            );
-                            *module_item = ModuleItem::Stmt(stmt);
-                        }
-                        ModuleDecl::ExportDefaultDecl(ExportDefaultDecl { decl, ..}) => {
-                            match decl {
-                                DefaultDecl::Class(class) => {
-                                    *module_item = ModuleItem::Stmt(Stmt::Decl(Decl::Class(ClassDecl {
-                                        ident: class.ident.unwrap_or_else(|| Ident::new(magic_identifier::mangle("default export").into(), DUMMY_SP)),
-                                        declare: false,
-                                        class: class.class
-                                    })))
-                                }
-                                DefaultDecl::Fn(fn_expr) => {
-                                    *module_item = ModuleItem::Stmt(Stmt::Decl(Decl::Fn(FnDecl {
-                                        ident: fn_expr.ident.unwrap_or_else(|| Ident::new(magic_identifier::mangle("default export").into(), DUMMY_SP)),
-                                        declare: false,
-                                        function: fn_expr.function
-                                    })))
-                                }
-                                DefaultDecl::TsInterfaceDecl(_) => {
-                                    panic!("typescript declarations are unexpected here");
-                                }
-                            }
-                        }
-                        ModuleDecl::ExportDecl(ExportDecl { decl, .. }) => {
-                            *module_item = ModuleItem::Stmt(Stmt::Decl(decl));
-                        }
-                        ModuleDecl::ExportNamed(_) => {
-                            // already removed
-                        }
-                        ModuleDecl::ExportAll(_) => {
-                            // already removed
-                        }
-                        ModuleDecl::Import(_) => {
-                            // already removed
-                        }
-                        _ => {
-                            // not matching
-                            *module_item = ModuleItem::ModuleDecl(module_decl);
-                        }
-                    }
-                } else {
-                    // not matching
-                    *module_item = item;
-                }
+                transform_or_report_module_item(
+                    module_item,
+                    &default_export_ident,
+                    default_export_kind,
+                    star_reexport_ident.as_deref(),
+                    source_ident,
+                );
             }),
         );
 
         Ok(CodeGeneration { visitors }.into())
     }
 }
+
+/// The magic identifier for an unnamed default export. A `prefix` namespaces
+/// it, so output can be combined with other code using the same mangling.
+pub(crate) fn default_export_name(prefix: Option<&str>) -> String {
+    match prefix {
+        Some(prefix) => magic_identifier::mangle(&format!("{prefix} default export")),
+        None => magic_identifier::mangle("default export"),
+    }
+}
+
+/// Removes the empty statements that replace removed module declarations.
+///
+/// A leading string literal statement is a directive, like `"use strict"`,
+/// and an empty statement ends the run of directives. So it is kept when it
+/// separates the directives from a string literal statement, which would
+/// become a directive without it.
+pub(crate) fn remove_empty_statements(program: &mut Program) {
+    let Program::Module(Module { body, .. }) = program else {
+        return;
+    };
+    let is_empty = |item: &ModuleItem| matches!(item, ModuleItem::Stmt(Stmt::Empty(_)));
+    let is_directive = |item: &ModuleItem| {
+        matches!(
+            item,
+            ModuleItem::Stmt(Stmt::Expr(ExprStmt {
+                expr: box Expr::Lit(Lit::Str(_)),
+                ..
+            }))
+        )
+    };
+
+    let directives = body.iter().take_while(|item| is_directive(item)).count();
+    let keep_separator = body[directives..]
+        .iter()
+        .find(|item| !is_empty(item))
+        .map_or(false, is_directive);
+    let mut index = 0;
+    body.retain(|item| {
+        let keep = !is_empty(item) || (keep_separator && index == directives);
+        index += 1;
+        keep
+    });
+}
+
+/// Like [transform_module_item], but reports a module declaration the
+/// transform doesn't know about as an issue on `source_ident`.
+fn transform_or_report_module_item(
+    module_item: &mut ModuleItem,
+    default_export_name: &str,
+    default_export_kind: VarDeclKind,
+    star_reexport_ident: Option<&str>,
+    source_ident: AssetIdentVc,
+) {
+    // The declaration is kept as is so the build can continue
+    if let Some(kind) = transform_module_item(
+        module_item,
+        default_export_name,
+        default_export_kind,
+        star_reexport_ident,
+    ) {
+        AnalyzeIssue {
+            code: None,
+            category: StringVc::cell("code generation".to_string()),
+            message: StringVc::cell(format!(
+                "{kind} was expected to be removed or replaced by the ESM transform, but was left \
+                 unchanged"
+            )),
+            source_ident,
+            severity: IssueSeverity::Warning.into(),
+            source: None,
+            title: StringVc::cell("unexpected module declaration".to_string()),
+        }
+        .cell()
+        .as_issue()
+        .emit();
+    }
+}
+
+/// Returns a description of the module declaration if it isn't one the
+/// transform knows about, in which case it is left unchanged.
+fn transform_module_item(
+    module_item: &mut ModuleItem,
+    default_export_name: &str,
+    default_export_kind: VarDeclKind,
+    star_reexport_ident: Option<&str>,
+) -> Option<&'static str> {
+    let item = replace(module_item, ModuleItem::Stmt(quote!(";" as Stmt)));
+    if let ModuleItem::ModuleDecl(module_decl) = item {
+        match module_decl {
+            ModuleDecl::ExportDefaultExpr(ExportDefaultExpr { box expr, .. }) => {
+                // A sequence binds looser than the initializer, so without
+                // parentheses only its first expression would be exported
+                let expr = match expr {
+                    Expr::Seq(seq) => Expr::Paren(ParenExpr {
+                        span: seq.span,
+                        expr: box Expr::Seq(seq),
+                    }),
+                    expr => expr,
+                };
+                *module_item = ModuleItem::Stmt(Stmt::Decl(Decl::Var(box VarDecl {
+                    span: DUMMY_SP,
+                    kind: default_export_kind,
+                    declare: false,
+                    decls: vec![VarDeclarator {
+                        span: DUMMY_SP,
+                        name: Pat::Ident(BindingIdent {
+                            id: Ident::new(default_export_name.into(), DUMMY_SP),
+                            type_ann: None,
+                        }),
+                        init: Some(box expr),
+                        definite: false,
+                    }],
+                })));
+            }
+            ModuleDecl::ExportDefaultDecl(ExportDefaultDecl { decl, .. }) => match decl {
+                DefaultDecl::Class(class) => {
+                    // `class.class` carries `abstract` and the other TS flags,
+                    // an ambient `declare class` can't be a default export
+                    *module_item = ModuleItem::Stmt(Stmt::Decl(Decl::Class(ClassDecl {
+                        ident: class
+                            .ident
+                            .unwrap_or_else(|| Ident::new(default_export_name.into(), DUMMY_SP)),
+                        declare: false,
+                        class: class.class,
+                    })))
+                }
+                DefaultDecl::Fn(fn_expr) => {
+                    *module_item = ModuleItem::Stmt(Stmt::Decl(Decl::Fn(FnDecl {
+                        ident: fn_expr
+                            .ident
+                            .unwrap_or_else(|| Ident::new(default_export_name.into(), DUMMY_SP)),
+                        declare: false,
+                        function: fn_expr.function,
+                    })))
+                }
+                DefaultDecl::TsInterfaceDecl(_) => {
+                    panic!("typescript declarations are unexpected here");
+                }
+            },
+            ModuleDecl::ExportDecl(ExportDecl { decl, .. }) => {
+                *module_item = ModuleItem::Stmt(Stmt::Decl(decl));
+            }
+            ModuleDecl::ExportNamed(_) => {
+                // already removed
+            }
+            ModuleDecl::ExportAll(_) => {
+                if let Some(ident) = star_reexport_ident {
+                    // Bindings that are already defined (local exports or
+                    // earlier re-exports) are kept by __turbopack_esm__
+                    let stmt = quote!(
+                        "for (const key in $module) { if (key !== \"default\") __turbopack_esm__({ \
+                         [key]: () => $module[key] }); }" as Stmt,
+                        module = Ident::new(ident.into(), DUMMY_SP)
+                    );
+                    *module_item = ModuleItem::Stmt(stmt);
+                }
+                // otherwise already removed
+            }
+            ModuleDecl::Import(_) => {
+                // already removed
+            }
+            module_decl => {
+                // not matching
+                let kind = match &module_decl {
+                    ModuleDecl::TsImportEquals(_) => "`import ... = ...`",
+                    ModuleDecl::TsExportAssignment(_) => "`export = ...`",
+                    ModuleDecl::TsNamespaceExport(_) => "`export as namespace ...`",
+                    _ => "a module declaration",
+                };
+                *module_item = ModuleItem::ModuleDecl(module_decl);
+                return Some(kind);
+            }
+        }
+    } else {
+        // not matching
+        *module_item = item;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use once_cell::sync::Lazy;
+    use swc_core::{
+        common::{errors::HANDLER, FileName, SourceMap},
+        ecma::{
+            ast::{EsVersion, Module},
+            codegen::{text_writer::JsWriter, Emitter},
+            parser::{parse_file_as_module, Syntax},
+            transforms::base::fixer::paren_remover,
+            visit::VisitMutWith,
+        },
+        testing::run_test,
+    };
+    use turbo_tasks::CompletionVc;
+    use turbo_tasks_fs::{FileSystem, FileSystemPathVc, NullFileSystem, NullFileSystemVc};
+    use turbo_tasks_testing::run;
+    use turbopack_core::{
+        ident::AssetIdentVc,
+        issue::{IssueSeverity, IssueVc},
+    };
+
+    use super::{
+        default_export_name, remove_empty_statements, transform_module_item,
+        transform_or_report_module_item, Program, VarDeclKind,
+    };
+
+    static REGISTER: Lazy<()> = Lazy::new(|| {
+        crate::register();
+        // The build script doesn't look into inline modules
+        TRANSFORM_AND_REPORT_FUNCTION.register(concat!(module_path!(), "::transform_and_report"));
+    });
+
+    /// Transforms each item of the TypeScript module `source`, reporting the
+    /// unexpected ones on `path`.
+    #[turbo_tasks::function]
+    async fn transform_and_report(source: &str, path: FileSystemPathVc) -> Result<CompletionVc> {
+        let source_ident = AssetIdentVc::from_path(path);
+        let default_export_name = default_export_name(None);
+        run_test(false, |cm, _handler| {
+            let fm = cm.new_source_file(FileName::Anon, source.into());
+            let mut m = parse_file_as_module(
+                &fm,
+                Syntax::Typescript(Default::default()),
+                EsVersion::latest(),
+                None,
+                &mut vec![],
+            )
+            .map_err(|err| HANDLER.with(|handler| err.into_diagnostic(handler).emit()))
+            .unwrap();
+
+            for item in m.body.iter_mut() {
+                transform_or_report_module_item(
+                    item,
+                    &default_export_name,
+                    VarDeclKind::Const,
+                    None,
+                    source_ident,
+                );
+            }
+            Ok(())
+        })
+        .unwrap();
+        Ok(CompletionVc::new())
+    }
+
+    #[derive(Default)]
+    struct Options<'a> {
+        syntax: Syntax,
+        prefix: Option<&'a str>,
+        star_reexport_ident: Option<&'a str>,
+        reassignable_default_export: bool,
+        remove_empty_statements: bool,
+    }
+
+    fn transform(source: &str, star_reexport_ident: Option<&str>) -> String {
+        let options = Options {
+            star_reexport_ident,
+            ..Default::default()
+        };
+        transform_with(source, options, |_| {}).0
+    }
+
+    /// Like `transform`, but without the parentheses from the source, as if
+    /// an earlier transform had removed them.
+    fn transform_without_parens(source: &str) -> String {
+        transform_with(source, Default::default(), |m| {
+            m.visit_mut_with(&mut paren_remover(None));
+        })
+        .0
+    }
+
+    /// Returns the output and the module declarations which were left
+    /// unchanged.
+    fn transform_with(
+        source: &str,
+        options: Options,
+        prepare: impl FnOnce(&mut Module),
+    ) -> (String, Vec<&'static str>) {
+        let default_export_name = default_export_name(options.prefix);
+        let default_export_kind = if options.reassignable_default_export {
+            VarDeclKind::Let
+        } else {
+            VarDeclKind::Const
+        };
+        let mut output = String::new();
+        let mut unexpected = Vec::new();
+        run_test(false, |cm, _handler| {
+            let fm = cm.new_source_file(FileName::Anon, source.into());
+            let mut m =
+                parse_file_as_module(&fm, options.syntax, EsVersion::latest(), None, &mut vec![])
+                    .map_err(|err| HANDLER.with(|handler| err.into_diagnostic(handler).emit()))
+                    .unwrap();
+            prepare(&mut m);
+
+            for item in m.body.iter_mut() {
+                unexpected.extend(transform_module_item(
+                    item,
+                    &default_export_name,
+                    default_export_kind,
+                    options.star_reexport_ident,
+                ));
+            }
+            if options.remove_empty_statements {
+                let mut program = Program::Module(m);
+                remove_empty_statements(&mut program);
+                let Program::Module(module) = program else {
+                    unreachable!()
+                };
+                m = module;
+            }
+
+            output = to_js(&m, &cm);
+            Ok(())
+        })
+        .unwrap();
+        (output, unexpected)
+    }
+
+    fn to_js(m: &Module, cm: &Arc<SourceMap>) -> String {
+        let mut bytes = Vec::new();
+        let mut emitter = Emitter {
+            cfg: swc_core::ecma::codegen::Config {
+                minify: true,
+                ..Default::default()
+            },
+            cm: cm.clone(),
+            comments: None,
+            wr: JsWriter::new(cm.clone(), "\n", &mut bytes, None),
+        };
+
+        emitter.emit_module(m).unwrap();
+
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn export_all_is_removed_by_default() {
+        assert_eq!(transform("export * from './x';", None), ";");
+    }
+
+    #[test]
+    fn export_all_emits_star_reexport_shim() {
+        let output = transform("export * from './x';\nexport const y = 1;", Some("x"));
+        assert!(output.starts_with("for(const key in x)"), "{output}");
+        assert!(
+            output.contains("__turbopack_esm__({[key]:()=>x[key]})"),
+            "{output}"
+        );
+        assert!(output.ends_with("const y=1;"), "{output}");
+    }
+
+    fn default_export(expr: &str) -> String {
+        format!("const {}={};", default_export_name(None), expr)
+    }
+
+    #[test]
+    fn default_export_name_uses_prefix() {
+        let with_prefix = |prefix| {
+            let options = Options {
+                prefix: Some(prefix),
+                ..Default::default()
+            };
+            transform_with("export default function() {}", options, |_| {}).0
+        };
+        let a = with_prefix("a");
+        let b = with_prefix("b");
+        assert_eq!(
+            a,
+            format!("function {}(){{}}", default_export_name(Some("a")))
+        );
+        assert_eq!(
+            b,
+            format!("function {}(){{}}", default_export_name(Some("b")))
+        );
+        assert_ne!(a, b);
+        assert_ne!(default_export_name(Some("a")), default_export_name(None));
+    }
+
+    #[test]
+    fn export_default_sequence_stays_one_value() {
+        assert_eq!(
+            transform("export default (a, b);", None),
+            default_export("(a,b)")
+        );
+        assert_eq!(
+            transform_without_parens("export default (a, b);"),
+            default_export("(a,b)")
+        );
+    }
+
+    #[test]
+    fn export_default_tagged_template() {
+        assert_eq!(
+            transform("export default tag`a${b}`;", None),
+            default_export("tag`a${b}`")
+        );
+    }
+
+    #[test]
+    fn export_default_iife() {
+        assert_eq!(
+            transform("export default (function() {})();", None),
+            default_export("(function(){})()")
+        );
+        // An initializer is an expression position, so the function doesn't
+        // need parentheses to be called
+        assert_eq!(
+            transform_without_parens("export default (function() {})();"),
+            default_export("function(){}()")
+        );
+    }
+
+    #[test]
+    fn export_default_reassignable() {
+        let options = Options {
+            reassignable_default_export: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            transform_with("export default 1;", options, |_| {}).0,
+            format!("let {}=1;", default_export_name(None))
+        );
+        assert_eq!(transform("export default 1;", None), default_export("1"));
+    }
+
+    #[test]
+    fn export_default_abstract_class() {
+        let options = Options {
+            syntax: Syntax::Typescript(Default::default()),
+            ..Default::default()
+        };
+        let (output, _) = transform_with("export default abstract class {}", options, |_| {});
+        assert_eq!(
+            output,
+            format!("abstract class {}{{}}", default_export_name(None))
+        );
+    }
+
+    #[test]
+    fn unexpected_module_decl_is_reported() {
+        let options = Options {
+            syntax: Syntax::Typescript(Default::default()),
+            ..Default::default()
+        };
+        let (output, unexpected) = transform_with("export = foo;", options, |_| {});
+        assert!(output.starts_with("export"), "{output}");
+        assert!(output.contains("foo"), "{output}");
+        assert_eq!(unexpected, vec!["`export = ...`"]);
+    }
+
+    #[tokio::test]
+    async fn unexpected_module_decl_emits_issue() {
+        run! {
+            let fs: NullFileSystemVc = NullFileSystem.into();
+            let result = transform_and_report(
+                "import a from './a';\nexport = a;",
+                fs.root().join("index.ts"),
+            );
+            let issues = IssueVc::peek_issues_with_path(result)
+                .await?
+                .strongly_consistent()
+                .await?
+                .get_plain_issues()
+                .await?;
+
+            assert_eq!(issues.len(), 1);
+            let issue = &issues[0];
+            assert_eq!(issue.severity, IssueSeverity::Warning);
+            assert_eq!(issue.title, "unexpected module declaration");
+            assert_eq!(issue.category, "code generation");
+            assert!(
+                issue.description.starts_with("`export = ...`"),
+                "{}",
+                issue.description
+            );
+        }
+    }
+
+    #[test]
+    fn known_module_decls_are_not_reported() {
+        let (_, unexpected) = transform_with(
+            "import a from './a';\nexport { a };\nexport const b = 1;\nexport default 2;",
+            Default::default(),
+            |_| {},
+        );
+        assert!(unexpected.is_empty(), "{unexpected:?}");
+    }
+
+    fn transform_and_clean_up(source: &str) -> String {
+        let options = Options {
+            remove_empty_statements: true,
+            ..Default::default()
+        };
+        transform_with(source, options, |_| {}).0
+    }
+
+    #[test]
+    fn empty_statements_are_removed() {
+        let output = transform_and_clean_up(
+            "import a from './a';\nexport { a };\nexport * from './b';\nexport const c = 1;\n;",
+        );
+        assert_eq!(output, "const c=1;");
+    }
+
+    #[test]
+    fn empty_statements_keep_directives() {
+        assert_eq!(
+            transform_and_clean_up("\"use client\";\nimport a from './a';\nfoo();"),
+            "\"use client\";foo();"
+        );
+        // Without the empty statement, "b" would become a directive
+        assert_eq!(
+            transform_and_clean_up("\"a\";\nimport x from './x';\n\"b\";"),
+            "\"a\";;\"b\";"
+        );
+        assert_eq!(
+            transform_and_clean_up("import x from './x';\n\"b\";"),
+            ";\"b\";"
+        );
+    }
+}
@@ -11,7 +11,7 @@ pub mod util;
 
 use std::{
     borrow::Cow,
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, VecDeque},
     future::Future,
     mem::take,
     pin::Pin,
@@ -103,12 +103,14 @@ use crate::{
     code_gen::{
         CodeGen, CodeGenerateableVc, CodeGenerateableWithAvailabilityInfoVc, CodeGenerateablesVc,
     },
-    magic_identifier,
     references::{
         cjs::{
             CjsRequireAssetReferenceVc, CjsRequireCacheAccess, CjsRequireResolveAssetReferenceVc,
         },
-        esm::{module_id::EsmModuleIdAssetReferenceVc, EsmBindingVc, EsmExportsVc},
+        esm::{
+            module_id::EsmModuleIdAssetReferenceVc, module_item::default_export_name, EsmBindingVc,
+            EsmExportsVc,
+        },
     },
     resolve::try_to_severity,
     tree_shake::{part_of_module, split},
@@ -417,6 +419,13 @@ pub(crate) async fn analyze_ecmascript_module(
                 analysis.add_reference(*r);
             }
 
+            let magic_identifier_prefix = match options.magic_identifier_prefix {
+                Some(prefix) => Some(prefix.await?),
+                None => None,
+            };
+            let default_export_name =
+                default_export_name(magic_identifier_prefix.as_deref().map(String::as_str));
+
             let (
                 mut var_graph,
                 webpack_runtime,
@@ -428,16 +437,24 @@ pub(crate) async fn analyze_ecmascript_module(
                 GLOBALS.set(globals, || {
                     // TODO migrate to effects
                     let mut visitor = AssetReferencesVisitor::new(
+                        source,
                         eval_context,
                         &import_references,
                         &mut analysis,
+                        options.star_reexport_shims,
+                        default_export_name,
+                        options.reassignable_default_export,
                     );
 
                     for (i, reexport) in eval_context.imports.reexports() {
                         let import_ref = import_references[i];
                         match reexport {
                             Reexport::Star => {
-                                visitor.esm_star_exports.push(import_ref);
+                                if let Some(shims) = &mut visitor.star_reexport_shims {
+                                    shims.push_back(import_ref);
+                                } else {
+                                    visitor.esm_star_exports.push(import_ref);
+                                }
                             }
                             Reexport::Namespace { exported: n } => {
                                 visitor.esm_exports.insert(
@@ -512,7 +529,15 @@ pub(crate) async fn analyze_ecmascript_module(
                 }
             }
 
-            let exports = if !esm_exports.is_empty() || !esm_star_exports.is_empty() {
+            let has_star_reexport_shims = options.star_reexport_shims
+                && eval_context
+                    .imports
+                    .reexports()
+                    .any(|(_, reexport)| matches!(reexport, Reexport::Star));
+            let exports = if !esm_exports.is_empty()
+                || !esm_star_exports.is_empty()
+                || has_star_reexport_shims
+            {
                 let esm_exports: EsmExportsVc = EsmExports {
                     exports: esm_exports,
                     star_exports: esm_star_exports,
@@ -2045,12 +2070,18 @@ impl StaticAnalyser {
 }
 
 struct AssetReferencesVisitor<'a> {
+    source: AssetVc,
     eval_context: &'a EvalContext,
     old_analyser: StaticAnalyser,
     import_references: &'a [EsmAssetReferenceVc],
     analysis: &'a mut AnalyzeEcmascriptModuleResultBuilder,
     esm_exports: BTreeMap<String, EsmExport>,
     esm_star_exports: Vec<EsmAssetReferenceVc>,
+    /// Star re-exports in source order which are emitted as runtime shims,
+    /// when enabled
+    star_reexport_shims: Option<VecDeque<EsmAssetReferenceVc>>,
+    default_export_name: String,
+    reassignable_default_export: bool,
     webpack_runtime: Option<(String, Span)>,
     webpack_entry: bool,
     webpack_chunks: Vec<Lit>,
@@ -2058,17 +2089,25 @@ struct AssetReferencesVisitor<'a> {
 
 impl<'a> AssetReferencesVisitor<'a> {
     fn new(
+        source: AssetVc,
         eval_context: &'a EvalContext,
         import_references: &'a [EsmAssetReferenceVc],
         analysis: &'a mut AnalyzeEcmascriptModuleResultBuilder,
+        star_reexport_shims: bool,
+        default_export_name: String,
+        reassignable_default_export: bool,
     ) -> Self {
         Self {
+            source,
             eval_context,
             old_analyser: StaticAnalyser::default(),
             import_references,
             analysis,
             esm_exports: BTreeMap::new(),
             esm_star_exports: Vec::new(),
+            star_reexport_shims: star_reexport_shims.then(|| VecDeque::with_capacity(0)),
+            default_export_name,
+            reassignable_default_export,
             webpack_runtime: None,
             webpack_entry: false,
             webpack_chunks: Vec::new(),
@@ -2131,6 +2170,28 @@ fn for_each_ident_in_pat(pat: &Pat, f: &mut impl FnMut(String)) {
     }
 }
 
+impl<'a> AssetReferencesVisitor<'a> {
+    /// Module declarations which the ESM transform can't handle, like the
+    /// TypeScript ones left in place when types aren't stripped. They are
+    /// still given an `EsmModuleItem` so that it reports them.
+    fn add_unexpected_module_decl(&mut self, ast_path: &AstNodePath<AstParentNodeRef<'_>>) {
+        let path = as_parent_path(ast_path);
+        // Declarations in a TypeScript namespace aren't module declarations
+        if matches!(
+            path.as_slice(),
+            [
+                AstParentKind::Program(_),
+                AstParentKind::Module(_),
+                AstParentKind::ModuleItem(_),
+                AstParentKind::ModuleDecl(_)
+            ]
+        ) {
+            self.analysis
+                .add_code_gen(EsmModuleItemVc::new(AstPathVc::cell(path), self.source));
+        }
+    }
+}
+
 impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
     fn visit_export_all<'ast: 'r, 'r>(
         &mut self,
@@ -2138,7 +2199,15 @@ impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
         ast_path: &mut AstNodePath<AstParentNodeRef<'r>>,
     ) {
         let path = AstPathVc::cell(as_parent_path(ast_path));
-        self.analysis.add_code_gen(EsmModuleItemVc::new(path));
+        let shim = self
+            .star_reexport_shims
+            .as_mut()
+            .and_then(|shims| shims.pop_front());
+        self.analysis.add_code_gen(if let Some(reference) = shim {
+            EsmModuleItemVc::new_star_reexport_shim(path, self.source, reference)
+        } else {
+            EsmModuleItemVc::new(path, self.source)
+        });
         export.visit_children_with_path(self, ast_path);
     }
 
@@ -2195,7 +2264,8 @@ impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
             }
         }
 
-        self.analysis.add_code_gen(EsmModuleItemVc::new(path));
+        self.analysis
+            .add_code_gen(EsmModuleItemVc::new(path, self.source));
         export.visit_children_with_path(self, ast_path);
     }
 
@@ -2208,10 +2278,10 @@ impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
             self.esm_exports
                 .insert(name.clone(), EsmExport::LocalBinding(name));
         });
-        self.analysis
-            .add_code_gen(EsmModuleItemVc::new(AstPathVc::cell(as_parent_path(
-                ast_path,
-            ))));
+        self.analysis.add_code_gen(EsmModuleItemVc::new(
+            AstPathVc::cell(as_parent_path(ast_path)),
+            self.source,
+        ));
         export.visit_children_with_path(self, ast_path);
     }
 
@@ -2222,12 +2292,15 @@ impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
     ) {
         self.esm_exports.insert(
             "default".to_string(),
-            EsmExport::LocalBinding(magic_identifier::mangle("default export")),
+            EsmExport::LocalBinding(self.default_export_name.clone()),
         );
         self.analysis
-            .add_code_gen(EsmModuleItemVc::new(AstPathVc::cell(as_parent_path(
-                ast_path,
-            ))));
+            .add_code_gen(EsmModuleItemVc::new_default_export(
+                AstPathVc::cell(as_parent_path(ast_path)),
+                self.source,
+                self.default_export_name.clone(),
+                self.reassignable_default_export,
+            ));
         export.visit_children_with_path(self, ast_path);
     }
 
@@ -2244,7 +2317,7 @@ impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
                         ident
                             .as_ref()
                             .map(|i| i.sym.to_string())
-                            .unwrap_or_else(|| magic_identifier::mangle("default export")),
+                            .unwrap_or_else(|| self.default_export_name.clone()),
                     ),
                 );
             }
@@ -2253,9 +2326,12 @@ impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
             }
         }
         self.analysis
-            .add_code_gen(EsmModuleItemVc::new(AstPathVc::cell(as_parent_path(
-                ast_path,
-            ))));
+            .add_code_gen(EsmModuleItemVc::new_default_export(
+                AstPathVc::cell(as_parent_path(ast_path)),
+                self.source,
+                self.default_export_name.clone(),
+                self.reassignable_default_export,
+            ));
         export.visit_children_with_path(self, ast_path);
     }
 
@@ -2300,7 +2376,35 @@ impl<'a> VisitAstPath for AssetReferencesVisitor<'a> {
                 }
             }
         }
-        self.analysis.add_code_gen(EsmModuleItemVc::new(path));
+        self.analysis
+            .add_code_gen(EsmModuleItemVc::new(path, self.source));
+    }
+
+    fn visit_ts_import_equals_decl<'ast: 'r, 'r>(
+        &mut self,
+        decl: &'ast TsImportEqualsDecl,
+        ast_path: &mut AstNodePath<AstParentNodeRef<'r>>,
+    ) {
+        self.add_unexpected_module_decl(ast_path);
+        decl.visit_children_with_path(self, ast_path);
+    }
+
+    fn visit_ts_export_assignment<'ast: 'r, 'r>(
+        &mut self,
+        export: &'ast TsExportAssignment,
+        ast_path: &mut AstNodePath<AstParentNodeRef<'r>>,
+    ) {
+        self.add_unexpected_module_decl(ast_path);
+        export.visit_children_with_path(self, ast_path);
+    }
+
+    fn visit_ts_namespace_export_decl<'ast: 'r, 'r>(
+        &mut self,
+        export: &'ast TsNamespaceExportDecl,
+        ast_path: &mut AstNodePath<AstParentNodeRef<'r>>,
+    ) {
+        self.add_unexpected_module_decl(ast_path);
+        export.visit_children_with_path(self, ast_path);
     }
 
     fn visit_var_declarator<'ast: 'r, 'r>(
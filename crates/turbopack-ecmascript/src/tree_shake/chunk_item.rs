@@ -54,6 +54,7 @@ impl EcmascriptChunkItem for EcmascriptModulePartChunkItem {
             parsed,
             module.full_module.ident(),
             availability_info,
+            module.full_module.await?.options.remove_empty_statements,
         ))
     }
 
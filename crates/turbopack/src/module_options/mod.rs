@@ -6,7 +6,7 @@ use anyhow::{Context, Result};
 pub use module_options_context::*;
 pub use module_rule::*;
 pub use rule_condition::*;
-use turbo_tasks::primitives::{OptionStringVc, StringsVc};
+use turbo_tasks::primitives::{OptionStringVc, StringVc, StringsVc};
 use turbo_tasks_fs::FileSystemPathVc;
 use turbopack_core::{
     reference_type::{ReferenceType, UrlReferenceSubType},
@@ -68,6 +68,10 @@ impl ModuleOptionsVc {
             ref enable_styled_components,
             enable_types,
             enable_tree_shaking,
+            enable_star_reexport_shims,
+            ref magic_identifier_prefix,
+            enable_reassignable_default_export,
+            enable_empty_statement_removal,
             ref enable_typescript_transform,
             ref decorators,
             enable_mdx,
@@ -144,6 +148,10 @@ impl ModuleOptionsVc {
         let ecmascript_options = EcmascriptOptions {
             split_into_parts: enable_tree_shaking,
             import_parts: enable_tree_shaking,
+            star_reexport_shims: enable_star_reexport_shims,
+            magic_identifier_prefix: magic_identifier_prefix.clone().map(StringVc::cell),
+            reassignable_default_export: enable_reassignable_default_export,
+            remove_empty_statements: enable_empty_statement_removal,
         };
 
         if let Some(env) = preset_env_versions {
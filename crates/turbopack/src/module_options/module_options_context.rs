@@ -239,6 +239,17 @@ pub struct ModuleOptionsContext {
     pub placeholder_for_future_extensions: (),
     #[serde(default)]
     pub enable_tree_shaking: bool,
+    #[serde(default)]
+    pub enable_star_reexport_shims: bool,
+    #[serde(default)]
+    /// Namespaces the magic identifiers used for unnamed default exports.
+    pub magic_identifier_prefix: Option<String>,
+    #[serde(default)]
+    pub enable_reassignable_default_export: bool,
+    #[serde(default)]
+    /// Removes the empty statements left behind by removed import and export
+    /// declarations.
+    pub enable_empty_statement_removal: bool,
 }
 
 #[turbo_tasks::value_impl]
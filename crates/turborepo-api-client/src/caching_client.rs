@@ -0,0 +1,211 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{APIClient, ClientError};
+
+/// Wraps an `APIClient` with a local directory of artifacts. Artifacts are
+/// read from the directory when present, and downloaded and stored there
+/// otherwise. Uploads are written to both.
+pub struct CachingClient {
+    client: APIClient,
+    cache_dir: PathBuf,
+}
+
+impl CachingClient {
+    pub fn new(client: APIClient, cache_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            client,
+            cache_dir: cache_dir.into(),
+        }
+    }
+
+    pub fn client(&self) -> &APIClient {
+        &self.client
+    }
+
+    /// Reads the artifact for `hash` from the cache directory, downloading
+    /// and storing it first if it isn't there.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn fetch_artifact(
+        &self,
+        hash: &str,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let path = self.artifact_path(hash)?;
+        match tokio::fs::read(&path).await {
+            Ok(artifact) => return Ok(artifact),
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("Error reading cached artifact {}", path.display()))
+            }
+        }
+
+        let artifact = self
+            .client
+            .fetch_artifact(hash, token, team_id, team_slug)
+            .await?;
+        self.store(&path, &artifact).await?;
+        Ok(artifact)
+    }
+
+    /// Stores the artifact for `hash` in the cache directory and uploads it.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn put_artifact(
+        &self,
+        hash: &str,
+        artifact: &[u8],
+        duration: u64,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<()> {
+        let path = self.artifact_path(hash)?;
+        self.store(&path, artifact).await?;
+        self.client
+            .put_artifact(hash, artifact, duration, token, team_id, team_slug)
+            .await
+    }
+
+    fn artifact_path(&self, hash: &str) -> Result<PathBuf, ClientError> {
+        if hash.is_empty() || !hash.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(ClientError::InvalidArtifactHash {
+                hash: hash.to_string(),
+            });
+        }
+        Ok(self.cache_dir.join(hash))
+    }
+
+    /// Writes to a temporary file first, so a concurrent read never sees a
+    /// partially written artifact.
+    async fn store(&self, path: &Path, artifact: &[u8]) -> Result<()> {
+        let tmp_path = path.with_extension(format!("tmp-{:x}", rand::random::<u64>()));
+        let result = async {
+            tokio::fs::create_dir_all(&self.cache_dir).await?;
+            tokio::fs::write(&tmp_path, artifact).await?;
+            tokio::fs::rename(&tmp_path, path).await
+        }
+        .await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+        result.with_context(|| format!("Error caching artifact at {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use httpmock::{
+        Method::{GET, PUT},
+        MockServer,
+    };
+
+    use super::CachingClient;
+    use crate::{APIClient, ClientError};
+
+    fn caching_client(server: &MockServer, cache_dir: &tempfile::TempDir) -> CachingClient {
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        CachingClient::new(client, cache_dir.path())
+    }
+
+    #[tokio::test]
+    async fn test_local_hit_skips_remote() {
+        let server = MockServer::start_async().await;
+        let remote = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v8/artifacts/abc123");
+                then.status(200).body("remote");
+            })
+            .await;
+        let cache_dir = tempfile::tempdir().unwrap();
+        std::fs::write(cache_dir.path().join("abc123"), "local").unwrap();
+
+        let client = caching_client(&server, &cache_dir);
+        let artifact = client
+            .fetch_artifact("abc123", Some("token"), "team_123", None)
+            .await
+            .unwrap();
+
+        assert_eq!(artifact, b"local");
+        assert_eq!(remote.hits_async().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_remote_hit_populates_local() {
+        let server = MockServer::start_async().await;
+        let remote = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v8/artifacts/abc123");
+                then.status(200).body("remote");
+            })
+            .await;
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let client = caching_client(&server, &cache_dir);
+        for _ in 0..2 {
+            let artifact = client
+                .fetch_artifact("abc123", Some("token"), "team_123", None)
+                .await
+                .unwrap();
+            assert_eq!(artifact, b"remote");
+        }
+
+        assert_eq!(remote.hits_async().await, 1);
+        assert_eq!(
+            std::fs::read(cache_dir.path().join("abc123")).unwrap(),
+            b"remote"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_put_writes_through() {
+        let server = MockServer::start_async().await;
+        let remote = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/v8/artifacts/abc123")
+                    .header("x-artifact-duration", "42")
+                    .body("artifact");
+                then.status(202);
+            })
+            .await;
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let client = caching_client(&server, &cache_dir);
+        client
+            .put_artifact("abc123", b"artifact", 42, Some("token"), "team_123", None)
+            .await
+            .unwrap();
+
+        remote.assert_async().await;
+        assert_eq!(
+            std::fs::read(cache_dir.path().join("abc123")).unwrap(),
+            b"artifact"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_hashes_that_are_not_file_names() {
+        let server = MockServer::start_async().await;
+        let cache_dir = tempfile::tempdir().unwrap();
+
+        let client = caching_client(&server, &cache_dir);
+        let err = client
+            .fetch_artifact("../abc123", Some("token"), "team_123", None)
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::InvalidArtifactHash { .. })
+        ));
+    }
+}
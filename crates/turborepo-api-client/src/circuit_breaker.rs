@@ -0,0 +1,146 @@
+use std::{sync::Mutex, time::Duration};
+
+use tokio::time::Instant;
+
+use crate::ClientError;
+
+/// Stops sending requests to a server that keeps failing.
+///
+/// After `failure_threshold` consecutive failures within `window`, the circuit
+/// opens and every request is rejected with `ClientError::CircuitOpen` until
+/// `cooldown` has elapsed. Once it has, requests are let through again; the
+/// first success closes the circuit while a failure reopens it immediately.
+pub(crate) struct CircuitBreaker {
+    failure_threshold: u32,
+    window: Duration,
+    cooldown: Duration,
+    state: Mutex<CircuitState>,
+}
+
+#[derive(Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    first_failure_at: Option<Instant>,
+    opened_at: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(failure_threshold: u32, window: Duration, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold,
+            window,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns an error if requests should currently be skipped.
+    pub(crate) fn check(&self) -> Result<(), ClientError> {
+        let state = self.state.lock().expect("circuit breaker lock poisoned");
+        match state.opened_at {
+            Some(opened_at) if opened_at.elapsed() < self.cooldown => Err(ClientError::CircuitOpen),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn record_success(&self) {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+        *state = CircuitState::default();
+    }
+
+    pub(crate) fn record_failure(&self) {
+        let mut state = self.state.lock().expect("circuit breaker lock poisoned");
+        let now = Instant::now();
+
+        // A failure while half-open means the server hasn't recovered yet.
+        if state.opened_at.is_some() {
+            state.opened_at = Some(now);
+            return;
+        }
+
+        match state.first_failure_at {
+            Some(first_failure_at) if now.duration_since(first_failure_at) <= self.window => {
+                state.consecutive_failures += 1;
+            }
+            _ => {
+                state.first_failure_at = Some(now);
+                state.consecutive_failures = 1;
+            }
+        }
+
+        if state.consecutive_failures >= self.failure_threshold {
+            state.opened_at = Some(now);
+        }
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(60), Duration::from_secs(30))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{circuit_breaker::CircuitBreaker, ClientError};
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(3, Duration::from_secs(10), Duration::from_secs(5))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_trips_after_consecutive_failures() {
+        let breaker = breaker();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+
+        breaker.record_failure();
+        assert!(matches!(breaker.check(), Err(ClientError::CircuitOpen)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_success_resets_failures() {
+        let breaker = breaker();
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_failures_outside_window_do_not_trip() {
+        let breaker = breaker();
+        breaker.record_failure();
+        breaker.record_failure();
+        tokio::time::advance(Duration::from_secs(11)).await;
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cooldown_and_recovery() {
+        let breaker = breaker();
+        for _ in 0..3 {
+            breaker.record_failure();
+        }
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert!(breaker.check().is_err());
+
+        // Half-open: a failed trial reopens the circuit for another cooldown
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(breaker.check().is_ok());
+        breaker.record_failure();
+        assert!(breaker.check().is_err());
+
+        // A successful trial closes it
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert!(breaker.check().is_ok());
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(breaker.check().is_ok());
+    }
+}
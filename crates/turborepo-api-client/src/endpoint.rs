@@ -0,0 +1,120 @@
+use reqwest::Url;
+
+/// A path on the API, with query parameters, relative to the client's base
+/// URL.
+///
+/// Dynamic path segments and query values are URL-encoded when the endpoint
+/// is resolved, so IDs and tokens can be passed as-is.
+#[derive(Debug, Clone)]
+pub(crate) struct Endpoint {
+    segments: Vec<String>,
+    query: Vec<(&'static str, String)>,
+}
+
+impl Endpoint {
+    /// Starts an endpoint from a fixed path such as `/v2/user`.
+    pub(crate) fn new(path: &'static str) -> Self {
+        Self {
+            segments: path
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| segment.to_string())
+                .collect(),
+            query: Vec::new(),
+        }
+    }
+
+    /// Appends a single path segment. Any `/` in `segment` is encoded rather
+    /// than starting a new segment.
+    pub(crate) fn segment(mut self, segment: &str) -> Self {
+        self.segments.push(segment.to_string());
+        self
+    }
+
+    pub(crate) fn query(mut self, key: &'static str, value: &str) -> Self {
+        self.query.push((key, value.to_string()));
+        self
+    }
+
+    /// Resolves the endpoint against `base_url`, keeping any path the base
+    /// URL already has.
+    pub(crate) fn to_url(&self, base_url: &Url) -> Url {
+        let mut url = base_url.clone();
+        url.path_segments_mut()
+            .expect("base URL is validated to be http(s)")
+            .pop_if_empty()
+            .extend(&self.segments);
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.query);
+        }
+        url
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::Url;
+
+    use super::Endpoint;
+
+    fn resolve(base_url: &str, endpoint: Endpoint) -> String {
+        endpoint.to_url(&Url::parse(base_url).unwrap()).to_string()
+    }
+
+    #[test]
+    fn test_path_composition() {
+        assert_eq!(
+            resolve("https://vercel.com", Endpoint::new("/v2/user")),
+            "https://vercel.com/v2/user"
+        );
+        assert_eq!(
+            resolve("https://vercel.com/api", Endpoint::new("/v2/user")),
+            "https://vercel.com/api/v2/user"
+        );
+        assert_eq!(
+            resolve("https://vercel.com/api/", Endpoint::new("/v2/user")),
+            "https://vercel.com/api/v2/user"
+        );
+        assert_eq!(
+            resolve(
+                "http://localhost:3000",
+                Endpoint::new("/v2/teams")
+                    .segment("team_123")
+                    .segment("members")
+                    .segment("me")
+            ),
+            "http://localhost:3000/v2/teams/team_123/members/me"
+        );
+    }
+
+    #[test]
+    fn test_query_composition() {
+        assert_eq!(
+            resolve(
+                "https://vercel.com/api",
+                Endpoint::new("/v8/artifacts/status")
+                    .query("teamSlug", "my-team")
+                    .query("teamId", "team_123")
+            ),
+            "https://vercel.com/api/v8/artifacts/status?teamSlug=my-team&teamId=team_123"
+        );
+    }
+
+    #[test]
+    fn test_special_characters_are_encoded() {
+        assert_eq!(
+            resolve(
+                "https://vercel.com",
+                Endpoint::new("/v2/teams").segment("a/b?c#d e")
+            ),
+            "https://vercel.com/v2/teams/a%2Fb%3Fc%23d%20e"
+        );
+        assert_eq!(
+            resolve(
+                "https://vercel.com",
+                Endpoint::new("/registration/verify").query("token", "a&b=c d+e")
+            ),
+            "https://vercel.com/registration/verify?token=a%26b%3Dc+d%2Be"
+        );
+    }
+}
@@ -0,0 +1,41 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// Too many requests have failed recently, so requests are being skipped
+    /// until the cooldown has elapsed.
+    #[error("skipping HTTP request, too many failures have occurred recently")]
+    CircuitOpen,
+    /// The response body was larger than the configured maximum.
+    #[error("response body exceeded the maximum size of {limit} bytes")]
+    ResponseTooLarge { limit: usize },
+    /// Every attempt at a request failed with a transient error. The last
+    /// error is the source of this one.
+    #[error("failed after {attempts} attempts")]
+    RetriesExhausted { attempts: u32 },
+    /// No data arrived on a connection for `idle_timeout`.
+    #[error("no data received for {idle_timeout:?}")]
+    ReadIdleTimeout { idle_timeout: std::time::Duration },
+    /// Shutdown was signalled while waiting to retry a request. The last
+    /// error is the source of this one.
+    #[error("request cancelled by shutdown")]
+    Cancelled,
+    /// The configured API URL could not be parsed or does not use http(s).
+    #[error("invalid API URL {url:?}: {reason}")]
+    InvalidBaseUrl { url: String, reason: String },
+    /// An artifact hash contained characters other than ASCII letters and
+    /// digits, so it can't safely be used as a file name.
+    #[error("invalid artifact hash {hash:?}")]
+    InvalidArtifactHash { hash: String },
+    /// A request was skipped because an earlier request in the same batch
+    /// was rejected as unauthorized.
+    #[error("skipped because the token was rejected")]
+    TokenRejected,
+    /// A method was passed no token and none has been set with
+    /// `APIClient::set_token`.
+    #[error("no token was given and none has been set")]
+    MissingToken,
+    /// A required environment variable is unset or empty.
+    #[error("{name} must be set")]
+    MissingEnvVar { name: &'static str },
+}
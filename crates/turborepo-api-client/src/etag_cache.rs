@@ -0,0 +1,40 @@
+use std::{collections::HashMap, sync::Mutex};
+
+/// Remembers response bodies by their `ETag` so repeated requests can be
+/// revalidated with `If-None-Match` instead of downloading the body again.
+///
+/// Entries are keyed by URL and token, since the same URL returns different
+/// data for different users.
+#[derive(Default)]
+pub(crate) struct EtagCache {
+    entries: Mutex<HashMap<(String, String), CachedResponse>>,
+}
+
+struct CachedResponse {
+    etag: String,
+    body: Vec<u8>,
+}
+
+impl EtagCache {
+    pub(crate) fn etag(&self, url: &str, token: &str) -> Option<String> {
+        let entries = self.entries.lock().expect("etag cache lock poisoned");
+        entries
+            .get(&(url.to_string(), token.to_string()))
+            .map(|cached| cached.etag.clone())
+    }
+
+    pub(crate) fn body(&self, url: &str, token: &str) -> Option<Vec<u8>> {
+        let entries = self.entries.lock().expect("etag cache lock poisoned");
+        entries
+            .get(&(url.to_string(), token.to_string()))
+            .map(|cached| cached.body.clone())
+    }
+
+    pub(crate) fn insert(&self, url: &str, token: &str, etag: String, body: Vec<u8>) {
+        let mut entries = self.entries.lock().expect("etag cache lock poisoned");
+        entries.insert(
+            (url.to_string(), token.to_string()),
+            CachedResponse { etag, body },
+        );
+    }
+}
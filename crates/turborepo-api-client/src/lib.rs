@@ -1,12 +1,60 @@
-use std::{env, future::Future};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    env,
+    future::Future,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use anyhow::{anyhow, Result};
-use reqwest::StatusCode;
-use serde::{Deserialize, Serialize};
+use anyhow::{anyhow, Context, Result};
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use log::warn;
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_NONE_MATCH, RANGE},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
 
-use crate::retry::retry_future;
+pub use crate::{
+    caching_client::CachingClient,
+    error::ClientError,
+    retry::RetryConfig,
+    timing::RequestTiming,
+    token::{Token, TokenProvider},
+};
+use crate::{
+    circuit_breaker::CircuitBreaker,
+    endpoint::Endpoint,
+    etag_cache::EtagCache,
+    request_id::REQUEST_ID_HEADER,
+    retry::{retry_future, FailureKind},
+    stream_json::{read_json_streaming, STREAMING_THRESHOLD_BYTES},
+    team_throttle::TeamThrottle,
+    timing::TimingCallback,
+    token::CachedTokenProvider,
+};
 
+mod caching_client;
+mod circuit_breaker;
+mod endpoint;
+mod error;
+mod etag_cache;
+mod logging;
+mod request_id;
 mod retry;
+mod stream_json;
+mod team_throttle;
+mod timing;
+mod token;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct VerifiedSsoUser {
@@ -30,9 +78,35 @@ pub enum CachingStatus {
     Paused,
 }
 
+impl CachingStatus {
+    /// A generic explanation of the status, used when the API doesn't provide
+    /// one.
+    pub fn default_message(&self) -> &'static str {
+        match self {
+            CachingStatus::Disabled => "remote caching disabled",
+            CachingStatus::Enabled => "remote caching enabled",
+            CachingStatus::OverLimit => "usage limit",
+            CachingStatus::Paused => "spending paused",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CachingStatusResponse {
     pub status: CachingStatus,
+    /// Human-readable reason for the status, if the API sent one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl CachingStatusResponse {
+    /// The message to show the user for this status, preferring the one sent
+    /// by the API.
+    pub fn user_message(&self) -> &str {
+        self.message
+            .as_deref()
+            .unwrap_or_else(|| self.status.default_message())
+    }
 }
 
 /// Membership is the relationship between the logged-in user and a particular
@@ -47,6 +121,10 @@ impl Membership {
     pub fn new(role: Role) -> Self {
         Self { role }
     }
+
+    pub fn role(&self) -> &Role {
+        &self.role
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -76,6 +154,53 @@ impl Team {
     }
 }
 
+/// The credentials read by `APIClient::from_env`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EnvCredentials {
+    /// From `TURBO_TOKEN`
+    pub token: String,
+    /// From `TURBO_TEAM`, if set
+    pub team: Option<String>,
+}
+
+/// What the cache knows about a stored artifact
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactMeta {
+    /// How long the task that produced the artifact took, in milliseconds
+    pub duration: Option<u64>,
+    /// Size of the artifact in bytes
+    pub size: Option<u64>,
+    /// Signature tag, if the artifact was signed
+    pub tag: Option<String>,
+}
+
+/// An artifact stored in the remote cache, as listed by
+/// `APIClient::list_artifacts`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactSummary {
+    pub hash: String,
+    /// Size of the artifact in bytes
+    pub size: u64,
+    /// When the artifact was stored, in milliseconds since the epoch
+    #[serde(rename = "createdAt")]
+    pub created_at: u64,
+}
+
+/// A page of `ArtifactSummary`s. `pagination.next` is passed as `until` to
+/// get the next page, and is missing on the last one.
+#[derive(Debug, Deserialize)]
+struct ArtifactsResponse {
+    #[serde(default)]
+    artifacts: Vec<ArtifactSummary>,
+    #[serde(default)]
+    pagination: Option<Pagination>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Pagination {
+    next: Option<u64>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TeamsResponse {
     pub teams: Vec<Team>,
@@ -96,187 +221,1040 @@ pub struct UserResponse {
     pub user: User,
 }
 
-pub struct APIClient {
+/// Only the parts of `UserResponse` needed to look up a username, so the rest
+/// of the user object is skipped rather than parsed.
+#[derive(Debug, Deserialize)]
+struct UsernameResponse {
+    user: Username,
+}
+
+#[derive(Debug, Deserialize)]
+struct Username {
+    username: String,
+}
+
+const DEFAULT_MAX_CONCURRENT_FAN_OUT: usize = 16;
+const DEFAULT_API_URL: &str = "https://vercel.com/api";
+const ARTIFACTS_PAGE_SIZE: u32 = 100;
+/// How long a prefetched caching status is kept for a `get_caching_status`
+/// call to use
+const PREFETCH_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// The settings the `reqwest::Client` is built from, kept so that it can be
+/// rebuilt when one of them changes.
+#[derive(Default)]
+struct ClientConfig {
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    resolve: Option<(String, SocketAddr)>,
+}
+
+impl ClientConfig {
+    fn build(&self) -> reqwest::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some((host, addr)) = &self.resolve {
+            builder = builder.resolve(host, *addr);
+        }
+        builder.build()
+    }
+}
+
+/// What is needed to send a request and read its response. It is cheap to
+/// clone, so requests running in the background, such as prefetches, go
+/// through the same request and response handling as everything else.
+#[derive(Clone)]
+struct Transport {
     client: reqwest::Client,
-    base_url: String,
+    max_response_bytes: Option<usize>,
+    circuit_breaker: Arc<CircuitBreaker>,
+    team_throttle: Arc<TeamThrottle>,
+    timing_callback: Option<TimingCallback>,
+}
+
+impl Transport {
+    /// Sends a request with a request ID, logging it and its response at
+    /// debug level.
+    async fn send(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, reqwest::Error> {
+        let request = request_builder
+            .header(REQUEST_ID_HEADER, request_id::current())
+            .build()?;
+        logging::log_request(&request);
+        let Some(timing_callback) = &self.timing_callback else {
+            let response = self
+                .client
+                .execute(request)
+                .await
+                .map_err(logging::redact_error)?;
+            logging::log_response(&response);
+            return Ok(response);
+        };
+
+        let method = request.method().clone();
+        let path = request.url().path().to_string();
+        let started_at = Instant::now();
+        let result = self.client.execute(request).await;
+        timing_callback(&RequestTiming {
+            method,
+            path,
+            status: result
+                .as_ref()
+                .map_or_else(|err| err.status(), |response| Some(response.status()))
+                .map(|status| status.as_u16()),
+            started_at,
+            finished_at: Instant::now(),
+        });
+        let response = result.map_err(logging::redact_error)?;
+        logging::log_response(&response);
+
+        Ok(response)
+    }
+
+    /// Reads and deserializes a JSON response body, enforcing
+    /// `max_response_bytes` if it is set.
+    async fn read_json<T: DeserializeOwned>(&self, response: reqwest::Response) -> Result<T> {
+        let body = self.read_body(response).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Reads a response body, enforcing `max_response_bytes` if it is set.
+    async fn read_body(&self, mut response: reqwest::Response) -> Result<Vec<u8>> {
+        let Some(limit) = self.max_response_bytes else {
+            let body = response.bytes().await?.to_vec();
+            logging::log_body(&body);
+            return Ok(body);
+        };
+
+        if response
+            .content_length()
+            .map_or(false, |length| length > limit as u64)
+        {
+            return Err(ClientError::ResponseTooLarge { limit }.into());
+        }
+
+        // The content length can be missing or wrong, so we also count the bytes
+        // as they arrive.
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(ClientError::ResponseTooLarge { limit }.into());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        logging::log_body(&body);
+        Ok(body)
+    }
+}
+
+/// A client for the Vercel API.
+///
+/// # Tokens
+///
+/// Methods making authenticated requests take a `token`, which is sent as
+/// is. If `token` is `None`, the token from `set_token` is sent instead, and
+/// the request fails if none has been set. If a token provider is set with
+/// `with_token_provider`, its token is always sent instead.
+pub struct APIClient {
+    transport: Transport,
+    client_config: ClientConfig,
+    read_idle_timeout: Option<Duration>,
+    base_url: reqwest::Url,
     user_agent: String,
+    retry_config: RetryConfig,
+    shutdown: CancellationToken,
+    etag_cache: EtagCache,
+    token_provider: Option<CachedTokenProvider>,
+    token: RwLock<Option<String>>,
+    /// Bounds the requests in flight across all batch methods, such as
+    /// `get_caching_statuses`
+    fan_out_limit: Semaphore,
+    max_concurrent_fan_out: usize,
+    team_allowlist: Option<HashSet<String>>,
+    /// In-flight or finished prefetches and when they were started, keyed by
+    /// URL and token. Each is used by at most one `get_caching_status` call.
+    prefetched_caching_statuses: Mutex<HashMap<(String, String), Prefetch>>,
 }
 
+type Prefetch = (Instant, JoinHandle<Option<CachingStatusResponse>>);
+
 impl APIClient {
-    pub async fn get_user(&self, token: &str) -> Result<UserResponse> {
+    /// Opens a connection to the API ahead of time so the first real request
+    /// doesn't pay for the TLS handshake. This is best-effort: any error,
+    /// including an error status, is ignored.
+    pub async fn warmup(&self) -> Result<()> {
+        let request_builder = self
+            .transport
+            .client
+            .head(self.base_url.clone())
+            .header("User-Agent", self.user_agent.clone());
+        let _ = self.transport.send(request_builder).await;
+
+        Ok(())
+    }
+
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_user(&self, token: Option<&str>) -> Result<UserResponse> {
+        self.get_user_inner(token, None).await
+    }
+
+    /// Same as `get_user`, but the request fails if it takes longer than
+    /// `deadline`, regardless of the client's timeout.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_user_with_deadline(
+        &self,
+        token: Option<&str>,
+        deadline: Duration,
+    ) -> Result<UserResponse> {
+        self.get_user_inner(token, Some(deadline)).await
+    }
+
+    async fn get_user_inner(
+        &self,
+        token: Option<&str>,
+        deadline: Option<Duration>,
+    ) -> Result<UserResponse> {
+        self.make_cached_request(&Endpoint::new("/v2/user"), token, deadline)
+            .await
+            .context("Error getting user")
+    }
+
+    /// Fetches just the current user's username. Use `get_user` for the full
+    /// user object.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_username(&self, token: Option<&str>) -> Result<String> {
+        let token = self.resolve_token(token).await?;
         let response = self
             .make_retryable_request(|| {
-                let url = self.make_url("/v2/user");
                 let request_builder = self
+                    .transport
                     .client
-                    .get(url)
+                    .get(self.make_url(&Endpoint::new("/v2/user")))
                     .header("User-Agent", self.user_agent.clone())
                     .header("Authorization", format!("Bearer {}", token))
                     .header("Content-Type", "application/json");
 
-                request_builder.send()
+                self.transport.send(request_builder)
             })
+            .await?;
+
+        let username_response: UsernameResponse = self
+            .transport
+            .read_json(response)
+            .await
+            .context("Error getting username")?;
+        Ok(username_response.user.username)
+    }
+
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_teams(&self, token: Option<&str>) -> Result<TeamsResponse> {
+        let mut teams: TeamsResponse = self
+            .make_cached_request(
+                &Endpoint::new("/v2/teams").query("limit", "100"),
+                token,
+                None,
+            )
+            .await
+            .context("Error getting teams")?;
+        if let Some(allowlist) = &self.team_allowlist {
+            teams.teams.retain(|team| allowlist.contains(&team.slug));
+        }
+
+        Ok(teams)
+    }
+
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_team(&self, token: Option<&str>, team_id: &str) -> Result<Option<Team>> {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let request_builder = self
+            .transport
+            .client
+            .get(self.make_url(&Endpoint::new("/v2/team").query("teamId", team_id)))
+            .header("User-Agent", self.user_agent.clone())
+            .header("Content-Type", "application/json")
+            .header("Authorization", format!("Bearer {}", token));
+        let response = self
+            .transport
+            .send(request_builder)
             .await?
             .error_for_status()?;
 
-        response.json().await.map_err(|err| {
-            anyhow!(
-                "Error getting user: {}",
-                err.status()
-                    .and_then(|status| status.canonical_reason())
-                    .unwrap_or(&err.to_string())
-            )
-        })
+        self.transport
+            .read_json(response)
+            .await
+            .context("Error getting team")
+    }
+
+    /// Fetches the current user's membership of a single team, which is
+    /// cheaper than fetching every team when the team is already known.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_team_membership(
+        &self,
+        token: Option<&str>,
+        team_id: &str,
+    ) -> Result<Membership> {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let endpoint = Endpoint::new("/v2/teams")
+            .segment(team_id)
+            .segment("members")
+            .segment("me");
+        let response = self
+            .make_retryable_request(|| {
+                let request_builder = self
+                    .transport
+                    .client
+                    .get(self.make_url(&endpoint))
+                    .header("User-Agent", self.user_agent.clone())
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {}", token));
+
+                self.transport.send(request_builder)
+            })
+            .await?;
+
+        self.transport
+            .read_json(response)
+            .await
+            .context("Error getting team membership")
     }
 
-    pub async fn get_teams(&self, token: &str) -> Result<TeamsResponse> {
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_caching_status(
+        &self,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<CachingStatusResponse> {
+        let token = self.resolve_token(token).await?;
+        let endpoint = Self::caching_status_endpoint(team_id, team_slug);
+
+        let prefetch = self
+            .prefetched_caching_statuses
+            .lock()
+            .expect("prefetch lock poisoned")
+            .remove(&(self.make_url(&endpoint).to_string(), token.to_string()));
+        if let Some((started_at, prefetch)) = prefetch {
+            if started_at.elapsed() < PREFETCH_MAX_AGE {
+                if let Ok(Some(response)) = prefetch.await {
+                    return Ok(response);
+                }
+            } else {
+                prefetch.abort();
+            }
+        }
+
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
         let response = self
             .make_retryable_request(|| {
                 let request_builder = self
+                    .transport
                     .client
-                    .get(self.make_url("/v2/teams?limit=100"))
+                    .get(self.make_url(&endpoint))
                     .header("User-Agent", self.user_agent.clone())
                     .header("Content-Type", "application/json")
                     .header("Authorization", format!("Bearer {}", token));
 
-                request_builder.send()
+                self.transport.send(request_builder)
             })
             .await?
             .error_for_status()?;
 
-        response.json().await.map_err(|err| {
-            anyhow!(
-                "Error getting teams: {}",
-                err.status()
-                    .and_then(|status| status.canonical_reason())
-                    .unwrap_or(&err.to_string())
-            )
-        })
+        self.transport
+            .read_json(response)
+            .await
+            .context("Error getting caching status")
     }
 
-    pub async fn get_team(&self, token: &str, team_id: &str) -> Result<Option<Team>> {
-        let response = self
+    /// Fetches the caching status of several teams, a few at a time. A failure
+    /// for one team is returned as that team's result rather than failing
+    /// the whole batch. Once the token is rejected as unauthorized, the teams
+    /// that haven't been requested yet fail with `ClientError::TokenRejected`
+    /// instead of sending requests that would fail the same way.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_caching_statuses(
+        &self,
+        token: Option<&str>,
+        team_ids: &[String],
+    ) -> HashMap<String, Result<CachingStatusResponse>> {
+        let token_rejected = AtomicBool::new(false);
+        let token_rejected = &token_rejected;
+        stream::iter(team_ids)
+            .map(|team_id| async move {
+                let _permit = self
+                    .fan_out_limit
+                    .acquire()
+                    .await
+                    .expect("fan-out semaphore is never closed");
+                if token_rejected.load(Ordering::SeqCst) {
+                    return (team_id.clone(), Err(ClientError::TokenRejected.into()));
+                }
+                let status = self.get_caching_status(token, team_id, None).await;
+                if status
+                    .as_ref()
+                    .map_or_else(Self::is_unauthorized, |_| false)
+                {
+                    token_rejected.store(true, Ordering::SeqCst);
+                }
+                (team_id.clone(), status)
+            })
+            .buffer_unordered(self.max_concurrent_fan_out)
+            .collect()
+            .await
+    }
+
+    /// The teams which have remote caching enabled. A team whose caching
+    /// status can't be fetched is logged and left out, rather than failing
+    /// the whole call.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn teams_with_caching_enabled(&self, token: Option<&str>) -> Result<Vec<Team>> {
+        let teams = self.get_teams(token).await?.teams;
+        let team_ids: Vec<_> = teams.iter().map(|team| team.id.clone()).collect();
+        let mut statuses = self.get_caching_statuses(token, &team_ids).await;
+
+        Ok(teams
+            .into_iter()
+            .filter(|team| match statuses.remove(&team.id) {
+                Some(Ok(response)) => matches!(response.status, CachingStatus::Enabled),
+                Some(Err(err)) => {
+                    warn!(
+                        "Error getting caching status for team {}: {:#}",
+                        team.id, err
+                    );
+                    false
+                }
+                None => false,
+            })
+            .collect())
+    }
+
+    /// Starts fetching the caching status in the background, so a later
+    /// `get_caching_status` call with the same arguments can use the result
+    /// instead of waiting for a request. If that call comes before the
+    /// prefetch has finished, it waits for the prefetch rather than sending a
+    /// second request. A failed prefetch is ignored, and the later call
+    /// makes its own request as usual. A prefetch that isn't used within
+    /// `PREFETCH_MAX_AGE` is discarded.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn prefetch_caching_status(
+        &self,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<()> {
+        let token = self.resolve_token(token).await?;
+        let url = self.make_url(&Self::caching_status_endpoint(team_id, team_slug));
+        let request_builder = self
+            .transport
             .client
-            .get(self.make_url("/v2/team"))
-            .query(&[("teamId", team_id)])
+            .get(url.clone())
             .header("User-Agent", self.user_agent.clone())
             .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", token))
-            .send()
-            .await?
-            .error_for_status()?;
+            .header("Authorization", format!("Bearer {}", token));
 
-        response.json().await.map_err(|err| {
-            anyhow!(
-                "Error getting team: {}",
-                err.status()
-                    .and_then(|status| status.canonical_reason())
-                    .unwrap_or(&err.to_string())
-            )
-        })
+        let transport = self.transport.clone();
+        let team_id = team_id.to_string();
+        let prefetch = tokio::spawn(async move {
+            transport.circuit_breaker.check().ok()?;
+            let _permit = transport.team_throttle.acquire(&team_id).await;
+            let response = transport
+                .send(request_builder)
+                .await
+                .ok()?
+                .error_for_status()
+                .ok()?;
+            transport.read_json(response).await.ok()
+        });
+
+        let mut prefetches = self
+            .prefetched_caching_statuses
+            .lock()
+            .expect("prefetch lock poisoned");
+        prefetches.retain(|_, (started_at, prefetch)| {
+            let is_stale = started_at.elapsed() >= PREFETCH_MAX_AGE;
+            if is_stale {
+                prefetch.abort();
+            }
+            !is_stale
+        });
+        if let Some((_, replaced)) = prefetches.insert(
+            (url.to_string(), token.to_string()),
+            (Instant::now(), prefetch),
+        ) {
+            replaced.abort();
+        }
+
+        Ok(())
     }
 
-    pub async fn get_caching_status(
+    fn caching_status_endpoint(team_id: &str, team_slug: Option<&str>) -> Endpoint {
+        let mut endpoint = Endpoint::new("/v8/artifacts/status");
+        if let Some(slug) = team_slug {
+            endpoint = endpoint.query("teamSlug", slug);
+        }
+        if team_id.starts_with("team_") {
+            endpoint = endpoint.query("teamId", team_id);
+        }
+        endpoint
+    }
+
+    /// Fetches what is known about the artifact for `hash` without
+    /// downloading it. Returns `None` if there is no such artifact.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn get_artifact_metadata(
         &self,
-        token: &str,
+        hash: &str,
+        token: Option<&str>,
         team_id: &str,
         team_slug: Option<&str>,
-    ) -> Result<CachingStatusResponse> {
-        let response = self
+    ) -> Result<Option<ArtifactMeta>> {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let endpoint = Self::artifact_endpoint(hash, team_id, team_slug);
+
+        let result = self
             .make_retryable_request(|| {
-                let mut request_builder = self
+                let request_builder = self
+                    .transport
                     .client
-                    .get(self.make_url("/v8/artifacts/status"))
+                    .head(self.make_url(&endpoint))
                     .header("User-Agent", self.user_agent.clone())
-                    .header("Content-Type", "application/json")
                     .header("Authorization", format!("Bearer {}", token));
 
-                if let Some(slug) = team_slug {
-                    request_builder = request_builder.query(&[("teamSlug", slug)]);
+                self.transport.send(request_builder)
+            })
+            .await;
+        let response = match result {
+            Ok(response) => response,
+            Err(err)
+                if err
+                    .downcast_ref::<reqwest::Error>()
+                    .and_then(|err| err.status())
+                    == Some(StatusCode::NOT_FOUND) =>
+            {
+                return Ok(None);
+            }
+            Err(err) => return Err(err),
+        };
+
+        let headers = response.headers();
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        Ok(Some(ArtifactMeta {
+            duration: header("x-artifact-duration").and_then(|duration| duration.parse().ok()),
+            size: header(CONTENT_LENGTH.as_str()).and_then(|size| size.parse().ok()),
+            tag: header("x-artifact-tag").map(|tag| tag.to_string()),
+        }))
+    }
+
+    /// Lists the artifacts stored for a team, following pagination until
+    /// `limit` artifacts have been fetched or there are no more.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn list_artifacts(
+        &self,
+        token: Option<&str>,
+        team_id: &str,
+        limit: Option<u32>,
+    ) -> Result<Vec<ArtifactSummary>> {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let limit = limit.map(|limit| limit as usize);
+
+        let mut artifacts = Vec::new();
+        let mut until = None;
+        while limit.map_or(true, |limit| artifacts.len() < limit) {
+            let page_size = limit.map_or(ARTIFACTS_PAGE_SIZE, |limit| {
+                (limit - artifacts.len()).min(ARTIFACTS_PAGE_SIZE as usize) as u32
+            });
+            let mut endpoint =
+                Endpoint::new("/v8/artifacts").query("limit", &page_size.to_string());
+            if team_id.starts_with("team_") {
+                endpoint = endpoint.query("teamId", team_id);
+            }
+            if let Some(until) = until {
+                endpoint = endpoint.query("until", &until.to_string());
+            }
+
+            let response = self
+                .make_retryable_request(|| {
+                    let request_builder = self
+                        .transport
+                        .client
+                        .get(self.make_url(&endpoint))
+                        .header("User-Agent", self.user_agent.clone())
+                        .header("Content-Type", "application/json")
+                        .header("Authorization", format!("Bearer {}", token));
+
+                    self.transport.send(request_builder)
+                })
+                .await?;
+            let page: ArtifactsResponse = self
+                .transport
+                .read_json(response)
+                .await
+                .context("Error listing artifacts")?;
+
+            let is_empty = page.artifacts.is_empty();
+            artifacts.extend(page.artifacts);
+            let next = page.pagination.and_then(|pagination| pagination.next);
+            // A cursor that doesn't move would return the same page forever
+            if next.is_none() || next == until || is_empty {
+                break;
+            }
+            until = next;
+        }
+        if let Some(limit) = limit {
+            artifacts.truncate(limit);
+        }
+
+        Ok(artifacts)
+    }
+
+    fn artifact_endpoint(hash: &str, team_id: &str, team_slug: Option<&str>) -> Endpoint {
+        let mut endpoint = Endpoint::new("/v8/artifacts").segment(hash);
+        if let Some(slug) = team_slug {
+            endpoint = endpoint.query("slug", slug);
+        }
+        if team_id.starts_with("team_") {
+            endpoint = endpoint.query("teamId", team_id);
+        }
+        endpoint
+    }
+
+    /// Downloads the artifact for `hash`. If the connection drops part way
+    /// through, the download resumes from the last received byte with a
+    /// `Range` request, up to the configured number of attempts. The whole
+    /// artifact, counted across resumes, must fit in `max_response_bytes` if
+    /// it is set.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn fetch_artifact(
+        &self,
+        hash: &str,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<Vec<u8>> {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let endpoint = Self::artifact_endpoint(hash, team_id, team_slug);
+
+        let request = |range_start: Option<usize>| {
+            let mut request_builder = self
+                .transport
+                .client
+                .get(self.make_url(&endpoint))
+                .header("User-Agent", self.user_agent.clone())
+                .header("Authorization", format!("Bearer {}", token));
+            if let Some(start) = range_start {
+                request_builder = request_builder.header(RANGE, format!("bytes={}-", start));
+            }
+
+            self.transport.send(request_builder)
+        };
+
+        let limit = self.transport.max_response_bytes;
+        let mut response = self.make_retryable_request(|| request(None)).await?;
+        if let Some(limit) = limit {
+            if response
+                .content_length()
+                .map_or(false, |length| length > limit as u64)
+            {
+                return Err(ClientError::ResponseTooLarge { limit }.into());
+            }
+        }
+        let mut body = Vec::new();
+        let mut resumes = 0;
+        loop {
+            match self.next_chunk(&mut response).await {
+                Ok(Some(chunk)) => {
+                    if let Some(limit) = limit {
+                        if body.len() + chunk.len() > limit {
+                            return Err(ClientError::ResponseTooLarge { limit }.into());
+                        }
+                    }
+                    body.extend_from_slice(&chunk);
                 }
-                if team_id.starts_with("team_") {
-                    request_builder = request_builder.query(&[("teamId", team_id)]);
+                Ok(None) => return Ok(body),
+                Err(err) if resumes < self.retry_config.max_attempts => {
+                    resumes += 1;
+                    let start = body.len();
+                    response = self
+                        .make_retryable_request(|| request(Some(start)))
+                        .await
+                        .with_context(|| format!("Error resuming download after: {}", err))?;
+                    if response.status() != StatusCode::PARTIAL_CONTENT {
+                        // The server ignored the range and is sending the whole artifact again
+                        body.clear();
+                    }
                 }
+                Err(err) => return Err(err).context("Error downloading artifact"),
+            }
+        }
+    }
 
-                request_builder.send()
-            })
-            .await?
-            .error_for_status()?;
+    /// Uploads the artifact for `hash`, recording that the task producing it
+    /// took `duration` milliseconds.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn put_artifact(
+        &self,
+        hash: &str,
+        artifact: &[u8],
+        duration: u64,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<()> {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let endpoint = Self::artifact_endpoint(hash, team_id, team_slug);
 
-        response.json().await.map_err(|err| {
-            anyhow!(
-                "Error getting caching status: {}",
-                err.status()
-                    .and_then(|status| status.canonical_reason())
-                    .unwrap_or(&err.to_string())
-            )
+        self.make_retryable_request(|| {
+            let request_builder = self
+                .transport
+                .client
+                .put(self.make_url(&endpoint))
+                .header("User-Agent", self.user_agent.clone())
+                .header("Authorization", format!("Bearer {}", token))
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header("x-artifact-duration", duration)
+                .body(artifact.to_vec());
+
+            self.transport.send(request_builder)
         })
+        .await
+        .context("Error uploading artifact")?;
+
+        Ok(())
+    }
+
+    /// Reads the next chunk of a response body, enforcing
+    /// `read_idle_timeout` if it is set.
+    async fn next_chunk(&self, response: &mut reqwest::Response) -> Result<Option<Bytes>> {
+        let Some(idle_timeout) = self.read_idle_timeout else {
+            return Ok(response.chunk().await?);
+        };
+        match tokio::time::timeout(idle_timeout, response.chunk()).await {
+            Ok(chunk) => Ok(chunk?),
+            Err(_) => Err(ClientError::ReadIdleTimeout { idle_timeout }.into()),
+        }
+    }
+
+    /// Uploads the log of the task that produced the artifact for `hash`,
+    /// sending each line as soon as `lines` yields it. The upload can't be
+    /// replayed, so it isn't retried.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn put_artifact_log_stream<S>(
+        &self,
+        hash: &str,
+        lines: S,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<()>
+    where
+        S: Stream<Item = String> + Send + Sync + 'static,
+    {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let endpoint = Self::artifact_endpoint(hash, team_id, team_slug).segment("logs");
+        let body = lines.map(|mut line| {
+            line.push('\n');
+            Ok::<_, Infallible>(line)
+        });
+
+        let request_builder = self
+            .transport
+            .client
+            .put(self.make_url(&endpoint))
+            .header("User-Agent", self.user_agent.clone())
+            .header("Authorization", format!("Bearer {}", token))
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(reqwest::Body::wrap_stream(body));
+        self.transport
+            .send(request_builder)
+            .await?
+            .error_for_status()
+            .context("Error uploading artifact log")?;
+
+        Ok(())
+    }
+
+    /// Deletes the artifact for `hash` from the remote cache. Returns `false`
+    /// if there was no such artifact. A retried delete could report a
+    /// successful first attempt as missing, so it isn't retried.
+    ///
+    /// `token` is resolved as described under [Tokens](APIClient#tokens).
+    pub async fn delete_artifact(
+        &self,
+        hash: &str,
+        token: Option<&str>,
+        team_id: &str,
+        team_slug: Option<&str>,
+    ) -> Result<bool> {
+        let token = self.resolve_token(token).await?;
+        let _permit = self.transport.team_throttle.acquire(team_id).await;
+        let endpoint = Self::artifact_endpoint(hash, team_id, team_slug);
+
+        let request_builder = self
+            .transport
+            .client
+            .delete(self.make_url(&endpoint))
+            .header("User-Agent", self.user_agent.clone())
+            .header("Authorization", format!("Bearer {}", token));
+        let response = self.transport.send(request_builder).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response
+            .error_for_status()
+            .context("Error deleting artifact")?;
+
+        Ok(true)
     }
 
     pub async fn verify_sso_token(&self, token: &str, token_name: &str) -> Result<VerifiedSsoUser> {
+        self.verify_sso_token_inner(token, token_name, None).await
+    }
+
+    /// Same as `verify_sso_token`, but the request fails if it takes longer
+    /// than `deadline`, regardless of the client's timeout.
+    pub async fn verify_sso_token_with_deadline(
+        &self,
+        token: &str,
+        token_name: &str,
+        deadline: Duration,
+    ) -> Result<VerifiedSsoUser> {
+        self.verify_sso_token_inner(token, token_name, Some(deadline))
+            .await
+    }
+
+    async fn verify_sso_token_inner(
+        &self,
+        token: &str,
+        token_name: &str,
+        deadline: Option<Duration>,
+    ) -> Result<VerifiedSsoUser> {
+        let endpoint = Endpoint::new("/registration/verify")
+            .query("token", token)
+            .query("tokenName", token_name);
         let response = self
             .make_retryable_request(|| {
                 let request_builder = self
+                    .transport
                     .client
-                    .get(self.make_url("/registration/verify"))
-                    .query(&[("token", token), ("tokenName", token_name)])
+                    .get(self.make_url(&endpoint))
                     .header("User-Agent", self.user_agent.clone());
 
-                request_builder.send()
+                self.transport
+                    .send(Self::with_deadline(request_builder, deadline))
             })
             .await?
             .error_for_status()?;
 
-        let verification_response: VerificationResponse = response.json().await.map_err(|err| {
-            anyhow!(
-                "Error verifying token: {}",
-                err.status()
-                    .and_then(|status| status.canonical_reason())
-                    .unwrap_or(&err.to_string())
-            )
-        })?;
+        let verification_response: VerificationResponse = self
+            .transport
+            .read_json(response)
+            .await
+            .context("Error verifying token")?;
         Ok(VerifiedSsoUser {
             token: verification_response.token,
             team_id: verification_response.team_id,
         })
     }
 
-    const RETRY_MAX: u32 = 2;
-
     async fn make_retryable_request<
         F: Future<Output = Result<reqwest::Response, reqwest::Error>>,
     >(
         &self,
         request_builder: impl Fn() -> F,
     ) -> Result<reqwest::Response> {
-        retry_future(Self::RETRY_MAX, request_builder, Self::should_retry_request).await
-    }
+        self.transport.circuit_breaker.check()?;
+
+        let result = request_id::scope(retry_future(
+            &self.retry_config,
+            &self.shutdown,
+            || {
+                let response = request_builder();
+                async move {
+                    response
+                        .await?
+                        .error_for_status()
+                        .map_err(logging::redact_error)
+                }
+            },
+            Self::classify_error,
+        ))
+        .await;
+        match &result {
+            Err(err) if Self::is_server_failure(err) => {
+                self.transport.circuit_breaker.record_failure()
+            }
+            _ => self.transport.circuit_breaker.record_success(),
+        }
 
-    fn should_retry_request(error: &reqwest::Error) -> bool {
-        if let Some(status) = error.status() {
-            if status == StatusCode::TOO_MANY_REQUESTS {
-                return true;
+        result.map_err(|err| {
+            // Only the path is included, since query parameters can contain tokens
+            let path = err
+                .downcast_ref::<reqwest::Error>()
+                .and_then(|err| err.url())
+                .map(|url| url.path().to_string());
+            match path {
+                Some(path) => err.context(format!("Error requesting {}", path)),
+                None => err,
             }
+        })
+    }
+
+    /// Makes an authenticated, retryable GET request and deserializes the
+    /// response body. If an earlier response had an `ETag`, it is sent as
+    /// `If-None-Match` and the cached body is reused on a 304. Large bodies
+    /// are decoded as they stream in and aren't cached.
+    async fn make_cached_request<T: DeserializeOwned + Send + 'static>(
+        &self,
+        endpoint: &Endpoint,
+        token: Option<&str>,
+        deadline: Option<Duration>,
+    ) -> Result<T> {
+        let token = self.resolve_token(token).await?;
+        let url = self.make_url(endpoint);
+        let url = url.as_str();
+        let etag = self.etag_cache.etag(url, &token);
+        let response = self
+            .make_retryable_request(|| {
+                let mut request_builder = self
+                    .transport
+                    .client
+                    .get(url)
+                    .header("User-Agent", self.user_agent.clone())
+                    .header("Authorization", format!("Bearer {}", token))
+                    .header("Content-Type", "application/json");
+                if let Some(etag) = &etag {
+                    request_builder = request_builder.header(IF_NONE_MATCH, etag);
+                }
+
+                self.transport
+                    .send(Self::with_deadline(request_builder, deadline))
+            })
+            .await?;
 
-            if status.as_u16() >= 500 && status.as_u16() != 501 {
-                return true;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            if let Some(body) = self.etag_cache.body(url, &token) {
+                return Ok(serde_json::from_slice(&body)?);
             }
         }
 
-        false
+        if response
+            .content_length()
+            .map_or(false, |length| length >= STREAMING_THRESHOLD_BYTES)
+        {
+            return read_json_streaming(response, self.transport.max_response_bytes).await;
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(|etag| etag.to_string());
+        let body = self.transport.read_body(response).await?;
+        if let Some(etag) = etag {
+            self.etag_cache.insert(url, &token, etag, body.clone());
+        }
+
+        Ok(serde_json::from_slice(&body)?)
     }
 
-    pub fn new(
-        base_url: impl AsRef<str>,
-        timeout: Option<u64>,
+    /// The bearer token to send: the token provider's if one is set, then
+    /// the token passed by the caller, and the token from `set_token` if the
+    /// caller passed `None`.
+    async fn resolve_token<'a>(&self, token: Option<&'a str>) -> Result<Cow<'a, str>> {
+        if let Some(provider) = &self.token_provider {
+            return Ok(Cow::Owned(provider.token().await?));
+        }
+        if let Some(token) = token {
+            return Ok(Cow::Borrowed(token));
+        }
+        let set_token = self.token.read().expect("token lock poisoned").clone();
+        Ok(Cow::Owned(set_token.ok_or(ClientError::MissingToken)?))
+    }
+
+    /// Whether an error indicates that the server is struggling, as opposed
+    /// to rejecting this particular request. Only connection failures and
+    /// 429/5xx responses count; timeouts are left out since a tight
+    /// per-call deadline says nothing about the server's health.
+    fn is_server_failure(error: &anyhow::Error) -> bool {
+        // Exhausted retries keep the last `reqwest::Error` as their source
+        match error.downcast_ref::<reqwest::Error>() {
+            Some(error) => match error.status() {
+                Some(status) => status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error(),
+                None => error.is_connect() && !error.is_timeout(),
+            },
+            None => false,
+        }
+    }
+
+    /// Whether a request failed because the token itself was rejected, as
+    /// opposed to lacking access to a particular team.
+    fn is_unauthorized(error: &anyhow::Error) -> bool {
+        error
+            .downcast_ref::<reqwest::Error>()
+            .and_then(|error| error.status())
+            == Some(StatusCode::UNAUTHORIZED)
+    }
+
+    fn classify_error(error: &reqwest::Error) -> FailureKind {
+        match error.status() {
+            Some(StatusCode::TOO_MANY_REQUESTS) => FailureKind::Transient,
+            Some(status) if status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED => {
+                FailureKind::Transient
+            }
+            // Bad requests, auth failures and missing resources will fail the
+            // same way every time, as will anything we don't know how to handle.
+            _ => FailureKind::Fatal,
+        }
+    }
+
+    /// Overrides the client's timeout for a single request.
+    fn with_deadline(
+        request_builder: reqwest::RequestBuilder,
+        deadline: Option<Duration>,
+    ) -> reqwest::RequestBuilder {
+        match deadline {
+            Some(deadline) => request_builder.timeout(deadline),
+            None => request_builder,
+        }
+    }
+
+    pub fn new(
+        base_url: impl AsRef<str>,
+        timeout: Option<u64>,
         version: &'static str,
     ) -> Result<Self> {
-        let client = match timeout {
-            Some(timeout) => reqwest::Client::builder()
-                .timeout(std::time::Duration::from_secs(timeout))
-                .build()?,
-            None => reqwest::Client::builder().build()?,
+        let base_url = Self::parse_base_url(base_url.as_ref())?;
+
+        let client_config = ClientConfig {
+            timeout: timeout.map(Duration::from_secs),
+            ..Default::default()
         };
+        let client = client_config.build()?;
 
         let user_agent = format!(
             "turbo {} {} {} {}",
@@ -286,13 +1264,1528 @@ impl APIClient {
             env::consts::ARCH
         );
         Ok(APIClient {
-            client,
-            base_url: base_url.as_ref().to_string(),
+            transport: Transport {
+                client,
+                max_response_bytes: None,
+                circuit_breaker: Arc::default(),
+                team_throttle: Arc::default(),
+                timing_callback: None,
+            },
+            client_config,
+            read_idle_timeout: None,
+            base_url,
             user_agent,
+            retry_config: RetryConfig::default(),
+            shutdown: CancellationToken::new(),
+            etag_cache: EtagCache::default(),
+            token_provider: None,
+            token: RwLock::default(),
+            fan_out_limit: Semaphore::new(DEFAULT_MAX_CONCURRENT_FAN_OUT),
+            max_concurrent_fan_out: DEFAULT_MAX_CONCURRENT_FAN_OUT,
+            team_allowlist: None,
+            prefetched_caching_statuses: Mutex::default(),
         })
     }
 
-    fn make_url(&self, endpoint: &str) -> String {
-        format!("{}{}", self.base_url, endpoint)
+    /// Creates a client for the API at `TURBO_API`, or the Vercel API if it
+    /// is unset, along with the token from `TURBO_TOKEN` and the team from
+    /// `TURBO_TEAM`. Fails if `TURBO_TOKEN` is unset.
+    pub fn from_env(version: &'static str) -> Result<(Self, EnvCredentials)> {
+        Self::from_environment(&env::vars().collect(), version)
+    }
+
+    fn from_environment(
+        environment: &HashMap<String, String>,
+        version: &'static str,
+    ) -> Result<(Self, EnvCredentials)> {
+        let var = |name: &str| {
+            environment
+                .get(name)
+                .filter(|value| !value.is_empty())
+                .cloned()
+        };
+        let token = var("TURBO_TOKEN").ok_or(ClientError::MissingEnvVar {
+            name: "TURBO_TOKEN",
+        })?;
+        let base_url = var("TURBO_API").unwrap_or_else(|| DEFAULT_API_URL.to_string());
+        let client = Self::new(base_url, None, version)?;
+
+        Ok((
+            client,
+            EnvCredentials {
+                token,
+                team: var("TURBO_TEAM"),
+            },
+        ))
+    }
+
+    fn parse_base_url(base_url: &str) -> Result<reqwest::Url, ClientError> {
+        let invalid = |reason: String| ClientError::InvalidBaseUrl {
+            url: base_url.to_string(),
+            reason,
+        };
+        let url = reqwest::Url::parse(base_url).map_err(|err| invalid(err.to_string()))?;
+        match url.scheme() {
+            "http" | "https" => Ok(url),
+            scheme => Err(invalid(format!("unsupported scheme {scheme:?}"))),
+        }
+    }
+
+    /// Overrides how requests are retried.
+    pub fn with_retry_config(mut self, retry_config: RetryConfig) -> Self {
+        self.retry_config = retry_config;
+        self
+    }
+
+    /// Stops waiting to retry failed requests once `shutdown` is cancelled,
+    /// returning `ClientError::Cancelled` instead.
+    pub fn with_shutdown_signal(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Gets the bearer token for each request from `provider` instead of
+    /// using the token passed to each method, so short-lived tokens can be
+    /// refreshed transparently. A token is reused until it expires.
+    pub fn with_token_provider(mut self, provider: impl TokenProvider + 'static) -> Self {
+        self.token_provider = Some(CachedTokenProvider::new(provider));
+        self
+    }
+
+    /// Sets the token sent when a method is passed no token, see
+    /// [Tokens](APIClient#tokens). This can be called while requests are in
+    /// flight, e.g. to rotate the token: requests that have already been sent
+    /// keep their token, and later ones use the new one.
+    pub fn set_token(&self, token: impl Into<String>) {
+        *self.token.write().expect("token lock poisoned") = Some(token.into());
+    }
+
+    /// Limits how many requests for the same team can be in flight at once.
+    /// Defaults to 64, and is at least 1.
+    pub fn with_max_concurrent_requests_per_team(mut self, max_concurrent_requests: usize) -> Self {
+        self.transport.team_throttle = Arc::new(TeamThrottle::new(max_concurrent_requests));
+        self
+    }
+
+    /// Limits how many requests the batch methods, such as
+    /// `get_caching_statuses`, have in flight at once. The limit is shared
+    /// by all concurrent batches. Defaults to 16, and is at least 1.
+    pub fn with_max_concurrent_fan_out(mut self, max_concurrent_fan_out: usize) -> Self {
+        // A limit of 0 would never let a request through
+        let max_concurrent_fan_out = max_concurrent_fan_out.max(1);
+        self.fan_out_limit = Semaphore::new(max_concurrent_fan_out);
+        self.max_concurrent_fan_out = max_concurrent_fan_out;
+        self
+    }
+
+    /// Calls `callback` with the timing of every request once its response
+    /// headers have arrived or it has failed.
+    pub fn with_timing_callback(
+        mut self,
+        callback: impl Fn(&RequestTiming) + Send + Sync + 'static,
+    ) -> Self {
+        self.transport.timing_callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Only returns teams whose slug is in `team_allowlist` from `get_teams`.
+    pub fn with_team_allowlist(mut self, team_allowlist: HashSet<String>) -> Self {
+        self.team_allowlist = Some(team_allowlist);
+        self
+    }
+
+    /// Connects to `addr` whenever the base URL's host is requested, skipping
+    /// DNS. The URL is left untouched, so the Host header and TLS SNI still
+    /// use the original hostname. The port in `addr` is ignored in favor of
+    /// the one in the base URL.
+    pub fn with_resolve_override(mut self, addr: SocketAddr) -> Result<Self> {
+        let host = self
+            .base_url
+            .host_str()
+            .ok_or_else(|| anyhow!("base URL {} has no host", self.base_url))?;
+        self.client_config.resolve = Some((host.to_string(), addr));
+        self.transport.client = self.client_config.build()?;
+        Ok(self)
+    }
+
+    /// Fails a request if connecting to the server takes longer than
+    /// `connect_timeout`, independently of the total timeout.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Result<Self> {
+        self.client_config.connect_timeout = Some(connect_timeout);
+        self.transport.client = self.client_config.build()?;
+        Ok(self)
+    }
+
+    /// Fails an artifact download if no data arrives for `read_idle_timeout`,
+    /// so a stalled connection is noticed long before the total timeout. A
+    /// stalled download is resumed like a dropped one.
+    pub fn with_read_idle_timeout(mut self, read_idle_timeout: Duration) -> Self {
+        self.read_idle_timeout = Some(read_idle_timeout);
+        self
+    }
+
+    /// Rejects any response body larger than `max_response_bytes`.
+    pub fn with_max_response_bytes(mut self, max_response_bytes: usize) -> Self {
+        self.transport.max_response_bytes = Some(max_response_bytes);
+        self
+    }
+
+    fn make_url(&self, endpoint: &Endpoint) -> reqwest::Url {
+        endpoint.to_url(&self.base_url)
+    }
+}
+
+impl Drop for APIClient {
+    fn drop(&mut self) {
+        // Prefetches that were never used would otherwise keep running. This
+        // mustn't panic, since it may run while unwinding.
+        let prefetches = self
+            .prefetched_caching_statuses
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (_, prefetch) in prefetches.values() {
+            prefetch.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        time::{Duration, Instant},
+    };
+
+    use async_trait::async_trait;
+    use httpmock::{
+        Method::{DELETE, GET, HEAD, PUT},
+        MockServer,
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        APIClient, ArtifactMeta, ArtifactSummary, CachingStatus, CachingStatusResponse,
+        ClientError, EnvCredentials, RetryConfig, Role, Token, TokenProvider, UsernameResponse,
+    };
+
+    #[tokio::test]
+    async fn test_get_team_membership() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/teams/team_123/members/me")
+                    .header("Authorization", "Bearer token");
+                then.status(200)
+                    .body(r#"{"uid":"user_1","role":"OWNER","confirmed":true}"#);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let membership = client
+            .get_team_membership(Some("token"), "team_123")
+            .await
+            .unwrap();
+
+        assert!(matches!(membership.role(), Role::Owner));
+        mock.assert_async().await;
+    }
+
+    #[test]
+    fn test_accepts_http_base_urls() {
+        for url in [
+            "https://vercel.com/api",
+            "http://localhost:3000",
+            "https://127.0.0.1:8080/",
+        ] {
+            assert!(APIClient::new(url, None, "test").is_ok(), "{url}");
+        }
+    }
+
+    #[test]
+    fn test_rejects_malformed_base_urls() {
+        for url in [
+            "ttps://vercel.com/api",
+            "ftp://vercel.com",
+            "vercel.com/api",
+            "",
+            "https://",
+        ] {
+            let err = APIClient::new(url, None, "test").unwrap_err();
+            assert!(
+                matches!(
+                    err.downcast_ref::<ClientError>(),
+                    Some(ClientError::InvalidBaseUrl { .. })
+                ),
+                "{url}: {err}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_warmup_ignores_error_status() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(HEAD).path("/");
+                then.status(404);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        assert!(client.warmup().await.is_ok());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_errors_do_not_include_token() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/registration/verify");
+                then.status(500);
+            })
+            .await;
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_retry_config(fast_retries());
+        let status_error = client
+            .verify_sso_token("secret-token", "laptop")
+            .await
+            .unwrap_err();
+
+        // Nothing listens on port 1, so the request fails to connect
+        let client = APIClient::new("http://127.0.0.1:1", None, "test")
+            .unwrap()
+            .with_retry_config(fast_retries());
+        let transport_error = client
+            .verify_sso_token("secret-token", "laptop")
+            .await
+            .unwrap_err();
+        assert!(transport_error
+            .downcast_ref::<reqwest::Error>()
+            .unwrap()
+            .is_connect());
+
+        for err in [status_error, transport_error] {
+            for output in [format!("{err:#}"), format!("{err:?}")] {
+                assert!(!output.contains("secret-token"), "{output}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deadline_overrides_client_timeout() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/user");
+                then.status(200).delay(Duration::from_secs(5));
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), Some(20), "test").unwrap();
+        let start = Instant::now();
+        let err = client
+            .get_user_with_deadline(Some("token"), Duration::from_millis(100))
+            .await
+            .unwrap_err();
+
+        assert!(err.downcast_ref::<reqwest::Error>().unwrap().is_timeout());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_timeouts_do_not_open_circuit() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/user");
+                then.status(200).delay(Duration::from_secs(5));
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), Some(20), "test")
+            .unwrap()
+            .with_retry_config(fast_retries());
+        // One more than the default breaker's failure threshold
+        for _ in 0..6 {
+            let err = client
+                .get_user_with_deadline(Some("token"), Duration::from_millis(20))
+                .await
+                .unwrap_err();
+            assert!(err.downcast_ref::<reqwest::Error>().unwrap().is_timeout());
+        }
+
+        assert!(client.transport.circuit_breaker.check().is_ok());
+    }
+
+    #[test]
+    fn test_caching_status_message() {
+        let response: CachingStatusResponse = serde_json::from_str(
+            r#"{ "status": "over_limit", "message": "You have exceeded your usage limit" }"#,
+        )
+        .unwrap();
+        assert!(matches!(response.status, CachingStatus::OverLimit));
+        assert_eq!(
+            response.user_message(),
+            "You have exceeded your usage limit"
+        );
+
+        let response: CachingStatusResponse =
+            serde_json::from_str(r#"{ "status": "paused" }"#).unwrap();
+        assert!(response.message.is_none());
+        assert_eq!(response.user_message(), "spending paused");
+    }
+
+    fn fast_retries() -> RetryConfig {
+        RetryConfig {
+            min_delay: Duration::ZERO,
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        for status in [400, 401, 403, 404] {
+            let server = MockServer::start_async().await;
+            let mock = server
+                .mock_async(|when, then| {
+                    when.method(GET).path("/v2/user");
+                    then.status(status);
+                })
+                .await;
+
+            let client = APIClient::new(server.base_url(), None, "test")
+                .unwrap()
+                .with_retry_config(fast_retries());
+            let err = client.get_user(Some("token")).await.unwrap_err();
+
+            assert_eq!(
+                err.downcast_ref::<reqwest::Error>()
+                    .and_then(|err| err.status())
+                    .map(|status| status.as_u16()),
+                Some(status)
+            );
+            assert_eq!(mock.hits_async().await, 1, "status {status} was retried");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/user");
+                then.status(503);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_retry_config(fast_retries());
+        let err = client.get_user(Some("token")).await.unwrap_err();
+        assert_eq!(mock.hits_async().await, 2);
+
+        let message = format!("{:#}", err);
+        assert!(message.contains("failed after 2 attempts"), "{message}");
+        assert!(message.contains("/v2/user"), "{message}");
+    }
+
+    #[test]
+    fn test_username_ignores_other_fields() {
+        let response: UsernameResponse = serde_json::from_str(
+            r#"{
+                "user": {
+                    "id": "user_id",
+                    "username": "my-username",
+                    "email": "me@example.com",
+                    "name": null,
+                    "createdAt": 0,
+                    "billing": { "plan": "hobby", "period": { "start": 0, "end": 1 } },
+                    "softBlock": null
+                },
+                "extra": [1, 2, 3]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(response.user.username, "my-username");
+    }
+
+    #[tokio::test]
+    async fn test_not_modified_reuses_cached_body() {
+        let server = MockServer::start_async().await;
+        let fresh = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/user")
+                    .header_missing("If-None-Match");
+                then.status(200).header("ETag", "\"v1\"").body(
+                    r#"{ "user": { "id": "user_id", "username": "my-username", "email": "me@example.com", "name": null, "createdAt": 0 } }"#,
+                );
+            })
+            .await;
+        let revalidated = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/user")
+                    .header("If-None-Match", "\"v1\"");
+                then.status(304);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let first = client.get_user(Some("token")).await.unwrap();
+        let second = client.get_user(Some("token")).await.unwrap();
+
+        assert_eq!(first.user.username, "my-username");
+        assert_eq!(second.user.username, "my-username");
+        fresh.assert_async().await;
+        revalidated.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_fetch_artifact_resumes_dropped_download() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let responses: [&[u8]; 2] = [
+                // Promises 10 bytes but closes the connection after 5
+                b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello",
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\ncontent-range: bytes \
+                  5-9/10\r\n\r\nworld",
+            ];
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = socket.read(&mut buf).await.unwrap();
+                requests.push(String::from_utf8_lossy(&buf[..n]).to_lowercase());
+                socket.write_all(response).await.unwrap();
+            }
+            requests
+        });
+
+        let client = APIClient::new(base_url, None, "test").unwrap();
+        let artifact = client
+            .fetch_artifact("hash", Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        let requests = server.await.unwrap();
+
+        assert_eq!(artifact, b"helloworld");
+        assert!(!requests[0].contains("range:"));
+        assert!(requests[1].contains("range: bytes=5-"), "{}", requests[1]);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_artifact_limit_counts_resumed_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let responses: [&[u8]; 2] = [
+                // Each response is within the limit, but not the two together
+                b"HTTP/1.1 200 OK\r\ncontent-length: 6\r\n\r\nhello",
+                b"HTTP/1.1 206 Partial Content\r\ncontent-length: 5\r\ncontent-range: bytes \
+                  5-9/10\r\n\r\nworld",
+            ];
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                socket.read(&mut buf).await.unwrap();
+                socket.write_all(response).await.unwrap();
+            }
+        });
+
+        let client = APIClient::new(base_url, None, "test")
+            .unwrap()
+            .with_max_response_bytes(8);
+        let err = client
+            .fetch_artifact("hash", Some("token"), "team_123", None)
+            .await
+            .unwrap_err();
+        server.await.unwrap();
+
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::ResponseTooLarge { limit: 8 })
+        ));
+    }
+
+    /// Hands out `token-1`, `token-2`, ... each valid for `lifetime`.
+    struct RotatingTokens {
+        refreshes: Arc<AtomicUsize>,
+        lifetime: Duration,
+    }
+
+    #[async_trait]
+    impl TokenProvider for RotatingTokens {
+        async fn token(&self) -> anyhow::Result<Token> {
+            let n = self.refreshes.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(Token {
+                value: format!("token-{n}"),
+                expires_at: Some(tokio::time::Instant::now() + self.lifetime),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_provider_refreshes_expired_tokens() {
+        let server = MockServer::start_async().await;
+        let mut mocks = Vec::new();
+        for n in 1..=2 {
+            mocks.push(
+                server
+                    .mock_async(|when, then| {
+                        when.method(GET)
+                            .path("/v2/user")
+                            .header("Authorization", format!("Bearer token-{n}"));
+                        then.status(200)
+                            .body(r#"{ "user": { "username": "my-username" } }"#);
+                    })
+                    .await,
+            );
+        }
+
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_token_provider(RotatingTokens {
+                refreshes: refreshes.clone(),
+                lifetime: Duration::ZERO,
+            });
+        client.get_username(Some("ignored")).await.unwrap();
+        client.get_username(Some("ignored")).await.unwrap();
+
+        assert_eq!(refreshes.load(Ordering::SeqCst), 2);
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_provider_reuses_valid_tokens() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/user")
+                    .header("Authorization", "Bearer token-1");
+                then.status(200)
+                    .body(r#"{ "user": { "username": "my-username" } }"#);
+            })
+            .await;
+
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_token_provider(RotatingTokens {
+                refreshes: refreshes.clone(),
+                lifetime: Duration::from_secs(3600),
+            });
+        client.get_username(Some("ignored")).await.unwrap();
+        client.get_username(Some("ignored")).await.unwrap();
+
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+        assert_eq!(mock.hits_async().await, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_set_token_while_requests_are_in_flight() {
+        let server = MockServer::start_async().await;
+        let mut mocks = Vec::new();
+        for token in ["initial", "rotated"] {
+            mocks.push(
+                server
+                    .mock_async(|when, then| {
+                        when.method(GET)
+                            .path("/v2/user")
+                            .header("Authorization", format!("Bearer {token}"));
+                        then.status(200)
+                            .delay(Duration::from_millis(50))
+                            .body(r#"{ "user": { "username": "my-username" } }"#);
+                    })
+                    .await,
+            );
+        }
+
+        let client = Arc::new(APIClient::new(server.base_url(), None, "test").unwrap());
+        client.set_token("initial");
+        let in_flight: Vec<_> = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.get_username(None).await })
+            })
+            .collect();
+        // Wait until every request has been sent with the initial token
+        while mocks[0].hits_async().await < 4 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        client.set_token("rotated");
+        let after: Vec<_> = (0..4)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.get_username(None).await })
+            })
+            .collect();
+        for handle in in_flight.into_iter().chain(after) {
+            assert_eq!(handle.await.unwrap().unwrap(), "my-username");
+        }
+
+        assert_eq!(mocks[0].hits_async().await, 4);
+        assert_eq!(mocks[1].hits_async().await, 4);
+    }
+
+    fn artifact_summaries(hashes: &[&str]) -> String {
+        let artifacts: Vec<_> = hashes
+            .iter()
+            .map(|hash| format!(r#"{{ "hash": "{hash}", "size": 1024, "createdAt": 1700 }}"#))
+            .collect();
+        artifacts.join(",")
+    }
+
+    #[tokio::test]
+    async fn test_list_artifacts_follows_pagination() {
+        let server = MockServer::start_async().await;
+        // Mocks are matched in the order they are created, so the later pages
+        // come first
+        let third_page = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v8/artifacts")
+                    .query_param("teamId", "team_123")
+                    .query_param("until", "2");
+                then.status(200).body(format!(
+                    r#"{{ "artifacts": [{}], "pagination": {{ "next": null }} }}"#,
+                    artifact_summaries(&["e"])
+                ));
+            })
+            .await;
+        let second_page = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v8/artifacts")
+                    .query_param("teamId", "team_123")
+                    .query_param("until", "1");
+                then.status(200).body(format!(
+                    r#"{{ "artifacts": [{}], "pagination": {{ "next": 2 }} }}"#,
+                    artifact_summaries(&["c", "d"])
+                ));
+            })
+            .await;
+        let first_page = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v8/artifacts")
+                    .query_param("teamId", "team_123");
+                then.status(200).body(format!(
+                    r#"{{ "artifacts": [{}], "pagination": {{ "next": 1 }} }}"#,
+                    artifact_summaries(&["a", "b"])
+                ));
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let artifacts = client
+            .list_artifacts(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        let hashes: Vec<_> = artifacts.iter().map(|a| a.hash.as_str()).collect();
+        assert_eq!(hashes, vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            artifacts[0],
+            ArtifactSummary {
+                hash: "a".to_string(),
+                size: 1024,
+                created_at: 1700,
+            }
+        );
+        for page in [&first_page, &second_page, &third_page] {
+            assert_eq!(page.hits_async().await, 1);
+        }
+
+        // The limit stops the pagination early
+        let artifacts = client
+            .list_artifacts(Some("token"), "team_123", Some(3))
+            .await
+            .unwrap();
+        assert_eq!(artifacts.len(), 3);
+        assert_eq!(third_page.hits_async().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_list_artifacts_stops_when_cursor_repeats() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v8/artifacts");
+                then.status(200).body(format!(
+                    r#"{{ "artifacts": [{}], "pagination": {{ "next": 5 }} }}"#,
+                    artifact_summaries(&["a"])
+                ));
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let artifacts = client
+            .list_artifacts(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        assert_eq!(artifacts.len(), 2);
+        assert_eq!(mock.hits_async().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_list_artifacts_empty() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v8/artifacts");
+                then.status(200).body(r#"{ "artifacts": [] }"#);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let artifacts = client
+            .list_artifacts(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        assert!(artifacts.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_explicit_token_takes_precedence_over_set_token() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/user")
+                    .header("Authorization", "Bearer explicit");
+                then.status(200)
+                    .body(r#"{ "user": { "username": "my-username" } }"#);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        client.set_token("set");
+        client.get_username(Some("explicit")).await.unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_empty_explicit_token_is_not_replaced() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v2/user")
+                    .header("Authorization", "Bearer set");
+                then.status(200)
+                    .body(r#"{ "user": { "username": "my-username" } }"#);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        client.set_token("set");
+        assert!(client.get_username(Some("")).await.is_err());
+        mock.assert_hits_async(0).await;
+    }
+
+    #[tokio::test]
+    async fn test_no_token_without_set_token() {
+        let client = APIClient::new("http://localhost:1", None, "test").unwrap();
+        let err = client.get_username(None).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::MissingToken)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_get_artifact_metadata() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(HEAD).path("/v8/artifacts/some-hash");
+                then.status(200)
+                    .header("x-artifact-duration", "1500")
+                    .header("Content-Length", "2048")
+                    .header("x-artifact-tag", "signature");
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let meta = client
+            .get_artifact_metadata("some-hash", Some("token"), "team_123", None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            meta,
+            Some(ArtifactMeta {
+                duration: Some(1500),
+                size: Some(2048),
+                tag: Some("signature".to_string()),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_artifact_metadata_not_found() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(HEAD).path("/v8/artifacts/missing-hash");
+                then.status(404);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let meta = client
+            .get_artifact_metadata("missing-hash", Some("token"), "team_123", None)
+            .await
+            .unwrap();
+
+        assert_eq!(meta, None);
+    }
+
+    #[tokio::test]
+    async fn test_team_allowlist_filters_teams() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/teams");
+                then.status(200).body(
+                    r#"{ "teams": [
+                        { "id": "team_1", "slug": "allowed", "name": "Allowed", "createdAt": 0, "created": "2023-01-01T00:00:00Z", "membership": { "role": "MEMBER" } },
+                        { "id": "team_2", "slug": "hidden", "name": "Hidden", "createdAt": 0, "created": "2023-01-01T00:00:00Z", "membership": { "role": "OWNER" } }
+                    ] }"#,
+                );
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        assert_eq!(
+            client.get_teams(Some("token")).await.unwrap().teams.len(),
+            2
+        );
+
+        let client = client.with_team_allowlist(HashSet::from(["allowed".to_string()]));
+        let teams = client.get_teams(Some("token")).await.unwrap().teams;
+        let slugs: Vec<_> = teams.iter().map(|team| team.slug.as_str()).collect();
+        assert_eq!(slugs, vec!["allowed"]);
+    }
+
+    #[tokio::test]
+    async fn test_large_teams_response_is_streamed() {
+        let teams: Vec<_> = (0..10_000)
+            .map(|n| {
+                format!(
+                    r#"{{ "id": "team_{n}", "slug": "team-{n}", "name": "Team {n}", "createdAt": 0, "created": "2023-01-01T00:00:00Z", "membership": {{ "role": "MEMBER" }} }}"#
+                )
+            })
+            .collect();
+        let body = format!(r#"{{ "teams": [{}] }}"#, teams.join(","));
+        assert!(body.len() as u64 > super::STREAMING_THRESHOLD_BYTES);
+
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/teams");
+                then.status(200).body(body);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let teams = client.get_teams(Some("token")).await.unwrap().teams;
+
+        assert_eq!(teams.len(), 10_000);
+        assert_eq!(teams[0].slug, "team-0");
+        assert_eq!(teams[9_999].id, "team_9999");
+    }
+
+    #[tokio::test]
+    async fn test_prefetched_caching_status_is_reused() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v8/artifacts/status")
+                    .query_param("teamId", "team_123");
+                then.status(200).body(r#"{ "status": "enabled" }"#);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        client
+            .prefetch_caching_status(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        while mock.hits_async().await == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let response = client
+            .get_caching_status(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        assert!(matches!(response.status, CachingStatus::Enabled));
+        assert_eq!(mock.hits_async().await, 1);
+
+        // The prefetched status is only used once
+        client
+            .get_caching_status(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        assert_eq!(mock.hits_async().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_uses_shared_request_handling() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET)
+                    .path("/v8/artifacts/status")
+                    .header_exists("x-turbo-request-id");
+                then.status(200).body(r#"{ "status": "enabled" }"#);
+            })
+            .await;
+
+        let timings = Arc::new(Mutex::new(Vec::new()));
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_max_response_bytes(8)
+            .with_timing_callback({
+                let timings = timings.clone();
+                move |timing| timings.lock().unwrap().push(timing.clone())
+            });
+        client
+            .prefetch_caching_status(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        while mock.hits_async().await == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // The prefetched body is over the limit, so the status is requested
+        // again, and fails the same way
+        let err = client
+            .get_caching_status(Some("token"), "team_123", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::ResponseTooLarge { limit: 8 })
+        ));
+        assert_eq!(mock.hits_async().await, 2);
+        assert_eq!(timings.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_get_caching_statuses() {
+        let server = MockServer::start_async().await;
+        for (team_id, status, body) in [
+            ("team_enabled", 200, r#"{ "status": "enabled" }"#),
+            ("team_disabled", 200, r#"{ "status": "disabled" }"#),
+            ("team_forbidden", 403, ""),
+        ] {
+            server
+                .mock_async(|when, then| {
+                    when.method(GET)
+                        .path("/v8/artifacts/status")
+                        .query_param("teamId", team_id);
+                    then.status(status).body(body);
+                })
+                .await;
+        }
+
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_retry_config(fast_retries());
+        let team_ids = ["team_enabled", "team_disabled", "team_forbidden"].map(String::from);
+        let statuses = client.get_caching_statuses(Some("token"), &team_ids).await;
+
+        assert_eq!(statuses.len(), 3);
+        assert!(matches!(
+            statuses["team_enabled"].as_ref().unwrap().status,
+            CachingStatus::Enabled
+        ));
+        assert!(matches!(
+            statuses["team_disabled"].as_ref().unwrap().status,
+            CachingStatus::Disabled
+        ));
+        assert!(statuses["team_forbidden"].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_teams_with_caching_enabled() {
+        let server = MockServer::start_async().await;
+        let team_ids = [
+            "team_enabled",
+            "team_disabled",
+            "team_forbidden",
+            "team_also_enabled",
+        ];
+        let teams: Vec<_> = team_ids
+            .iter()
+            .map(|id| {
+                format!(
+                    r#"{{ "id": "{id}", "slug": "{id}", "name": "{id}", "createdAt": 0, "created": "2023-01-01T00:00:00Z", "membership": {{ "role": "MEMBER" }} }}"#
+                )
+            })
+            .collect();
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/teams");
+                then.status(200)
+                    .body(format!(r#"{{ "teams": [{}] }}"#, teams.join(",")));
+            })
+            .await;
+        for (team_id, status, body) in [
+            ("team_enabled", 200, r#"{ "status": "enabled" }"#),
+            ("team_disabled", 200, r#"{ "status": "disabled" }"#),
+            ("team_forbidden", 403, ""),
+            ("team_also_enabled", 200, r#"{ "status": "enabled" }"#),
+        ] {
+            server
+                .mock_async(|when, then| {
+                    when.method(GET)
+                        .path("/v8/artifacts/status")
+                        .query_param("teamId", team_id);
+                    then.status(status).body(body);
+                })
+                .await;
+        }
+
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_retry_config(fast_retries());
+        let teams = client
+            .teams_with_caching_enabled(Some("token"))
+            .await
+            .unwrap();
+
+        let ids: Vec<_> = teams.iter().map(|team| team.id.as_str()).collect();
+        assert_eq!(ids, vec!["team_enabled", "team_also_enabled"]);
+    }
+
+    #[tokio::test]
+    async fn test_get_caching_statuses_stops_after_unauthorized() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v8/artifacts/status");
+                then.status(401);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_max_concurrent_fan_out(1);
+        let team_ids = ["team_a", "team_b", "team_c"].map(String::from);
+        let statuses = client.get_caching_statuses(Some("token"), &team_ids).await;
+
+        assert_eq!(mock.hits_async().await, 1);
+        let rejected = statuses
+            .values()
+            .filter(|status| {
+                matches!(
+                    status.as_ref().unwrap_err().downcast_ref::<ClientError>(),
+                    Some(ClientError::TokenRejected)
+                )
+            })
+            .count();
+        assert_eq!(statuses.len(), 3);
+        assert_eq!(rejected, 2);
+    }
+
+    #[tokio::test]
+    async fn test_zero_fan_out_limit_still_makes_progress() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v8/artifacts/status");
+                then.status(200).body(r#"{ "status": "enabled" }"#);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_max_concurrent_fan_out(0);
+        let team_ids = ["team_a", "team_b"].map(String::from);
+        let statuses = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.get_caching_statuses(Some("token"), &team_ids),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(statuses.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_max_response_bytes() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/teams");
+                then.status(200).body(format!(
+                    r#"{{ "teams": [], "padding": "{}" }}"#,
+                    "a".repeat(2048)
+                ));
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        assert!(client.get_teams(Some("token")).await.is_ok());
+
+        let client = client.with_max_response_bytes(1024);
+        let err = client.get_teams(Some("token")).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::ResponseTooLarge { limit: 1024 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_override_keeps_hostname() {
+        let server = MockServer::start_async().await;
+        let host = format!("api.vercel.com:{}", server.port());
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/user").header("Host", &host);
+                then.status(200).body(
+                    r#"{ "user": { "id": "user_id", "username": "my-username", "email": "me@example.com", "name": null, "createdAt": 0 } }"#,
+                );
+            })
+            .await;
+
+        let client = APIClient::new(format!("http://{host}"), None, "test")
+            .unwrap()
+            .with_resolve_override(*server.address())
+            .unwrap();
+        let response = client.get_user(Some("token")).await.unwrap();
+        assert_eq!(response.user.username, "my-username");
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_get_caching_statuses_honors_fan_out_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let server = {
+            let in_flight = in_flight.clone();
+            let max_in_flight = max_in_flight.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (mut socket, _) = listener.accept().await.unwrap();
+                    let in_flight = in_flight.clone();
+                    let max_in_flight = max_in_flight.clone();
+                    tokio::spawn(async move {
+                        let mut buf = vec![0; 4096];
+                        socket.read(&mut buf).await.unwrap();
+                        let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                        max_in_flight.fetch_max(current, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                        let body = r#"{ "status": "enabled" }"#;
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: \
+                             close\r\n\r\n{body}",
+                            body.len()
+                        );
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    });
+                }
+            })
+        };
+
+        let client = APIClient::new(base_url, None, "test")
+            .unwrap()
+            .with_max_concurrent_fan_out(3);
+        let team_ids: Vec<_> = (0..30).map(|n| format!("team_{n}")).collect();
+        let statuses = client.get_caching_statuses(Some("token"), &team_ids).await;
+        server.abort();
+
+        assert_eq!(statuses.len(), 30);
+        assert!(statuses.values().all(|status| status.is_ok()));
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[tokio::test]
+    async fn test_retries_reuse_request_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let body = r#"{ "user": { "id": "user_id", "username": "my-username", "email": "me@example.com", "name": null, "createdAt": 0 } }"#;
+            let responses = [
+                "HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: \
+                 close\r\n\r\n"
+                    .to_string(),
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                ),
+            ];
+            let mut request_ids = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = socket.read(&mut buf).await.unwrap();
+                let request = String::from_utf8_lossy(&buf[..n]).to_lowercase();
+                request_ids.push(
+                    request
+                        .lines()
+                        .find_map(|line| line.strip_prefix("x-turbo-request-id: "))
+                        .map(str::to_string),
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            request_ids
+        });
+
+        let client = APIClient::new(base_url, None, "test")
+            .unwrap()
+            .with_retry_config(fast_retries());
+        client.get_user(Some("token")).await.unwrap();
+        let request_ids = server.await.unwrap();
+
+        assert!(request_ids[0].is_some());
+        assert_eq!(request_ids[0], request_ids[1]);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_cancels_retry_backoff() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v2/user");
+                then.status(503);
+            })
+            .await;
+
+        let shutdown = CancellationToken::new();
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_shutdown_signal(shutdown.clone());
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            shutdown.cancel();
+        });
+        let start = Instant::now();
+        let err = client.get_user(Some("token")).await.unwrap_err();
+
+        // The default schedule would wait 2 seconds before retrying
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::Cancelled)
+        ));
+        assert_eq!(mock.hits_async().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_put_artifact_log_stream() {
+        let server = MockServer::start_async().await;
+        let mock = server
+            .mock_async(|when, then| {
+                when.method(PUT)
+                    .path("/v8/artifacts/hash/logs")
+                    .query_param("teamId", "team_123")
+                    .body("line 1\nline 2\nline 3\n");
+                then.status(200);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        let (sender, receiver) = tokio::sync::mpsc::channel(1);
+        let lines = futures::stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|line| (line, receiver))
+        });
+        let producer = tokio::spawn(async move {
+            for n in 1..=3 {
+                sender.send(format!("line {n}")).await.unwrap();
+            }
+        });
+        client
+            .put_artifact_log_stream("hash", lines, Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        producer.await.unwrap();
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_artifact() {
+        let server = MockServer::start_async().await;
+        let present = server
+            .mock_async(|when, then| {
+                when.method(DELETE)
+                    .path("/v8/artifacts/present")
+                    .query_param("teamId", "team_123");
+                then.status(200);
+            })
+            .await;
+        let absent = server
+            .mock_async(|when, then| {
+                when.method(DELETE).path("/v8/artifacts/absent");
+                then.status(404);
+            })
+            .await;
+        let failing = server
+            .mock_async(|when, then| {
+                when.method(DELETE).path("/v8/artifacts/failing");
+                then.status(500);
+            })
+            .await;
+
+        let client = APIClient::new(server.base_url(), None, "test").unwrap();
+        assert!(client
+            .delete_artifact("present", Some("token"), "team_123", None)
+            .await
+            .unwrap());
+        assert!(!client
+            .delete_artifact("absent", Some("token"), "team_123", None)
+            .await
+            .unwrap());
+        assert!(client
+            .delete_artifact("failing", Some("token"), "team_123", None)
+            .await
+            .is_err());
+
+        present.assert_async().await;
+        absent.assert_async().await;
+        // Deletes aren't retried
+        failing.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_read_idle_timeout_detects_stalled_download() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            // Keeps the connections open, so only the idle timeout can end them
+            let mut sockets = Vec::new();
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                socket.read(&mut buf).await.unwrap();
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello")
+                    .await
+                    .unwrap();
+                sockets.push(socket);
+            }
+        });
+
+        let client = APIClient::new(base_url, Some(20), "test")
+            .unwrap()
+            .with_retry_config(fast_retries())
+            .with_read_idle_timeout(Duration::from_millis(100));
+        let start = Instant::now();
+        let err = client
+            .fetch_artifact("hash", Some("token"), "team_123", None)
+            .await
+            .unwrap_err();
+        server.abort();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            err.chain().any(|err| matches!(
+                err.downcast_ref::<ClientError>(),
+                Some(ClientError::ReadIdleTimeout { .. })
+            )),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn test_from_environment() {
+        let environment = HashMap::from([
+            ("TURBO_API".to_string(), "http://localhost:3000".to_string()),
+            ("TURBO_TOKEN".to_string(), "my-token".to_string()),
+            ("TURBO_TEAM".to_string(), "my-team".to_string()),
+        ]);
+        let (client, credentials) = APIClient::from_environment(&environment, "test").unwrap();
+        assert_eq!(client.base_url.as_str(), "http://localhost:3000/");
+        assert_eq!(
+            credentials,
+            EnvCredentials {
+                token: "my-token".to_string(),
+                team: Some("my-team".to_string()),
+            }
+        );
+
+        let environment = HashMap::from([("TURBO_TOKEN".to_string(), "my-token".to_string())]);
+        let (client, credentials) = APIClient::from_environment(&environment, "test").unwrap();
+        assert_eq!(client.base_url.as_str(), "https://vercel.com/api");
+        assert_eq!(credentials.team, None);
+    }
+
+    #[test]
+    fn test_from_environment_requires_token() {
+        for environment in [
+            HashMap::new(),
+            HashMap::from([("TURBO_TOKEN".to_string(), String::new())]),
+        ] {
+            let err = APIClient::from_environment(&environment, "test").unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<ClientError>(),
+                Some(ClientError::MissingEnvVar {
+                    name: "TURBO_TOKEN"
+                })
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timing_callback() {
+        let server = MockServer::start_async().await;
+        server
+            .mock_async(|when, then| {
+                when.method(GET).path("/v8/artifacts/status");
+                then.status(200)
+                    .delay(Duration::from_millis(50))
+                    .body(r#"{ "status": "enabled" }"#);
+            })
+            .await;
+
+        let timings = Arc::new(Mutex::new(Vec::new()));
+        let client = APIClient::new(server.base_url(), None, "test")
+            .unwrap()
+            .with_timing_callback({
+                let timings = timings.clone();
+                move |timing| timings.lock().unwrap().push(timing.clone())
+            });
+        let before = Instant::now();
+        client
+            .get_caching_status(Some("token"), "team_123", None)
+            .await
+            .unwrap();
+        let after = Instant::now();
+
+        let timings = timings.lock().unwrap();
+        assert_eq!(timings.len(), 1);
+        let timing = &timings[0];
+        assert_eq!(timing.method, reqwest::Method::GET);
+        assert_eq!(timing.path, "/v8/artifacts/status");
+        assert_eq!(timing.status, Some(200));
+        assert!(before <= timing.started_at);
+        assert!(timing.started_at <= timing.finished_at);
+        assert!(timing.finished_at <= after);
+        assert!(timing.time_to_headers() >= Duration::from_millis(50));
     }
 }
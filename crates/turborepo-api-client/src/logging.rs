@@ -0,0 +1,138 @@
+//! Debug logging of requests and responses. Credentials are redacted: the
+//! `Authorization` header and `token` query parameter are never logged, nor
+//! included in errors.
+
+use log::{debug, log_enabled, trace, Level};
+use reqwest::{header::HeaderMap, Url};
+
+/// Response bodies longer than this are truncated when logged.
+const MAX_LOGGED_BODY_BYTES: usize = 1024;
+
+const REDACTED: &str = "***";
+
+pub(crate) fn log_request(request: &reqwest::Request) {
+    if log_enabled!(Level::Debug) {
+        debug!(
+            "{}",
+            describe(request.method().as_str(), request.url(), request.headers())
+        );
+    }
+}
+
+pub(crate) fn log_response(response: &reqwest::Response) {
+    if log_enabled!(Level::Debug) {
+        debug!(
+            "{}",
+            describe(
+                response.status().as_str(),
+                response.url(),
+                response.headers()
+            )
+        );
+    }
+}
+
+pub(crate) fn log_body(body: &[u8]) {
+    if log_enabled!(Level::Trace) {
+        trace!("response body: {}", truncate_body(body));
+    }
+}
+
+/// Redacts the URL that `error` includes when it is formatted.
+pub(crate) fn redact_error(error: reqwest::Error) -> reqwest::Error {
+    match error.url().map(redact_url) {
+        Some(url) => error.with_url(url),
+        None => error,
+    }
+}
+
+fn describe(prefix: &str, url: &Url, headers: &HeaderMap) -> String {
+    let mut description = format!("{} {}", prefix, redact_url(url));
+    for (name, value) in headers {
+        let value = if *name == reqwest::header::AUTHORIZATION {
+            match value.to_str() {
+                Ok(value) if value.starts_with("Bearer ") => format!("Bearer {}", REDACTED),
+                _ => REDACTED.to_string(),
+            }
+        } else {
+            String::from_utf8_lossy(value.as_bytes()).into_owned()
+        };
+        description.push_str(&format!("\n  {}: {}", name, value));
+    }
+    description
+}
+
+fn redact_url(url: &Url) -> Url {
+    if !url.query_pairs().any(|(key, _)| key == "token") {
+        return url.clone();
+    }
+
+    let mut redacted = url.clone();
+    redacted
+        .query_pairs_mut()
+        .clear()
+        .extend_pairs(url.query_pairs().map(|(key, value)| {
+            if key == "token" {
+                (key, REDACTED.into())
+            } else {
+                (key, value)
+            }
+        }));
+    redacted
+}
+
+fn truncate_body(body: &[u8]) -> String {
+    if body.len() <= MAX_LOGGED_BODY_BYTES {
+        String::from_utf8_lossy(body).into_owned()
+    } else {
+        format!(
+            "{}... ({} bytes total)",
+            String::from_utf8_lossy(&body[..MAX_LOGGED_BODY_BYTES]),
+            body.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE},
+        Url,
+    };
+
+    use super::{describe, truncate_body, MAX_LOGGED_BODY_BYTES};
+
+    #[test]
+    fn test_token_is_redacted() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Bearer secret-token"),
+        );
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        let url = Url::parse(
+            "https://vercel.com/api/registration/verify?token=secret-token&tokenName=laptop",
+        )
+        .unwrap();
+
+        let output = describe("GET", &url, &headers);
+
+        assert!(!output.contains("secret-token"), "{output}");
+        assert!(output.contains("authorization: Bearer ***"), "{output}");
+        assert!(output.contains("token=***"), "{output}");
+        assert!(output.contains("tokenName=laptop"), "{output}");
+        assert!(
+            output.contains("content-type: application/json"),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_long_bodies_are_truncated() {
+        let body = vec![b'a'; MAX_LOGGED_BODY_BYTES * 2];
+        let output = truncate_body(&body);
+
+        assert!(output.starts_with(&"a".repeat(MAX_LOGGED_BODY_BYTES)));
+        assert!(output.ends_with(&format!("({} bytes total)", body.len())));
+    }
+}
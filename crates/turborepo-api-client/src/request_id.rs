@@ -0,0 +1,58 @@
+use std::future::Future;
+
+/// Sent with every request, so a request can be found in the server's logs.
+pub(crate) const REQUEST_ID_HEADER: &str = "x-turbo-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Runs `future` with a new request ID, which every request it sends reuses.
+/// This groups the retries of a request under the same ID.
+pub(crate) async fn scope<F: Future>(future: F) -> F::Output {
+    REQUEST_ID.scope(generate(), future).await
+}
+
+/// The ID of the enclosing `scope`, or a new one outside of a scope.
+pub(crate) fn current() -> String {
+    REQUEST_ID
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| generate())
+}
+
+/// A random (version 4) UUID.
+fn generate() -> String {
+    let mut bits = rand::random::<u128>();
+    bits = (bits & !(0xf << 76)) | (0x4 << 76);
+    bits = (bits & !(0x3 << 62)) | (0x2 << 62);
+    format!(
+        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+        bits >> 96,
+        (bits >> 80) & 0xffff,
+        (bits >> 64) & 0xffff,
+        (bits >> 48) & 0xffff,
+        bits & 0xffff_ffff_ffff
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::{current, generate, scope};
+
+    #[test]
+    fn test_generate_is_uuid_v4() {
+        let id = generate();
+        let groups: Vec<_> = id.split('-').map(str::len).collect();
+        assert_eq!(groups, [8, 4, 4, 4, 12]);
+        assert_eq!(&id[14..15], "4");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"), "{id}");
+        assert_ne!(id, generate());
+    }
+
+    #[tokio::test]
+    async fn test_scope_reuses_id() {
+        let (first, second) = scope(async { (current(), current()) }).await;
+        assert_eq!(first, second);
+        assert_ne!(current(), current());
+    }
+}
@@ -1,49 +1,220 @@
-use std::future::Future;
+use std::{future::Future, time::Duration};
 
 use anyhow::anyhow;
+use rand::Rng;
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 
-const MIN_SLEEP_TIME_SECS: u64 = 2;
-const MAX_SLEEP_TIME_SECS: u64 = 10;
+use crate::ClientError;
 
-/// Retries a future until `max_retries` is reached, the `should_retry` function
-/// returns false, or the future succeeds. Uses an exponential backoff with a
-/// base of 2 to delay between retries.
+/// Controls how many times a request is attempted and how long to wait
+/// between attempts.
+///
+/// The delay after the `n`th failed attempt (starting at 0) is
+/// `base_delay * multiplier^n`, clamped between `min_delay` and `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryConfig {
+    /// Maximum number of attempts
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: f64,
+    /// Randomly shortens each delay by up to half, so that many clients
+    /// retrying at once don't do so in lockstep
+    pub jitter: bool,
+}
+
+impl RetryConfig {
+    fn delay(&self, retry_count: u32) -> Duration {
+        let secs = self.base_delay.as_secs_f64() * self.multiplier.powi(retry_count as i32);
+        let delay = Duration::try_from_secs_f64(secs)
+            .unwrap_or(self.max_delay)
+            .max(self.min_delay)
+            .min(self.max_delay);
+
+        if self.jitter {
+            delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 2,
+            base_delay: Duration::from_secs(1),
+            min_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: false,
+        }
+    }
+}
+
+/// How a failed attempt should be handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The failure may go away on its own, so the attempt can be retried
+    Transient,
+    /// Retrying won't help, so the error is returned immediately without
+    /// further attempts or delays
+    Fatal,
+}
+
+/// Retries a future until `config.max_attempts` is reached, the `classify`
+/// function reports a fatal error, or the future succeeds. Delays between
+/// attempts follow the backoff schedule in `config`, and are cut short if
+/// `shutdown` is cancelled.
 ///
 /// # Arguments
 ///
-/// * `max_retries`: Maximum number of retries
+/// * `config`: Number of attempts and backoff schedule
+/// * `shutdown`: Aborts retrying when cancelled
 /// * `future_generator`: Function to call to generate the future for each retry
-/// * `should_retry`: Determines if a retry should be attempted based on the
-///   error
+/// * `classify`: Determines if a retry should be attempted based on the error
 ///
 /// returns: Result<T, Error>
 pub async fn retry_future<T, E: Into<anyhow::Error>, F: Future<Output = Result<T, E>>>(
-    max_retries: u32,
+    config: &RetryConfig,
+    shutdown: &CancellationToken,
     future_generator: impl Fn() -> F,
-    should_retry: impl Fn(&E) -> bool,
+    classify: impl Fn(&E) -> FailureKind,
 ) -> Result<T, anyhow::Error> {
     let mut last_error = None;
-    for retry_count in 0..max_retries {
+    for retry_count in 0..config.max_attempts {
         let future = future_generator();
         match future.await {
             Ok(value) => return Ok(value),
             Err(err) => {
-                if !should_retry(&err) {
+                if classify(&err) == FailureKind::Fatal {
                     return Err(err.into());
                 }
                 last_error = Some(err);
             }
         }
+        if retry_count + 1 == config.max_attempts {
+            // There is no attempt left to wait for
+            break;
+        }
 
-        let sleep_period = (2_u64)
-            .pow(retry_count)
-            .clamp(MIN_SLEEP_TIME_SECS, MAX_SLEEP_TIME_SECS);
-        sleep(std::time::Duration::from_secs(sleep_period)).await;
+        tokio::select! {
+            _ = sleep(config.delay(retry_count)) => {}
+            _ = shutdown.cancelled() => {
+                let err = last_error.expect("only failed attempts are retried");
+                return Err(err.into().context(ClientError::Cancelled));
+            }
+        }
+    }
+
+    let attempts = config.max_attempts;
+    Err(match last_error {
+        Some(err) => err
+            .into()
+            .context(ClientError::RetriesExhausted { attempts }),
+        None => anyhow!(ClientError::RetriesExhausted { attempts }),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, time::Duration};
+
+    use anyhow::anyhow;
+    use tokio::time::{sleep, Instant};
+    use tokio_util::sync::CancellationToken;
+
+    use crate::{
+        retry::{retry_future, FailureKind, RetryConfig},
+        ClientError,
+    };
+
+    #[test]
+    fn test_default_schedule() {
+        let config = RetryConfig::default();
+        let delays: Vec<_> = (0..5).map(|n| config.delay(n).as_secs()).collect();
+        assert_eq!(delays, vec![2, 2, 4, 8, 10]);
     }
 
-    Err(anyhow!(
-        "skipping HTTP Request, too many failures have occurred.\nLast error: {}",
-        last_error.unwrap().into()
-    ))
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_follows_configured_schedule() {
+        let config = RetryConfig {
+            max_attempts: 4,
+            base_delay: Duration::from_secs(1),
+            min_delay: Duration::ZERO,
+            max_delay: Duration::from_secs(5),
+            multiplier: 3.0,
+            jitter: false,
+        };
+        let attempts = RefCell::new(Vec::new());
+
+        let started_at = Instant::now();
+        let result = retry_future(
+            &config,
+            &CancellationToken::new(),
+            || {
+                attempts.borrow_mut().push(Instant::now());
+                async { Err::<(), _>(anyhow!("failed")) }
+            },
+            |_| FailureKind::Transient,
+        )
+        .await;
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::RetriesExhausted { attempts: 4 })
+        ));
+        assert_eq!(err.root_cause().to_string(), "failed");
+
+        let attempts = attempts.into_inner();
+        let delays: Vec<_> = attempts
+            .windows(2)
+            .map(|pair| pair[1].duration_since(pair[0]))
+            .collect();
+        assert_eq!(
+            delays,
+            vec![
+                Duration::from_secs(1),
+                Duration::from_secs(3),
+                Duration::from_secs(5)
+            ]
+        );
+        // No delay follows the last attempt
+        assert_eq!(started_at.elapsed(), Duration::from_secs(9));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_cancels_backoff() {
+        let config = RetryConfig {
+            max_attempts: 3,
+            base_delay: Duration::from_secs(60),
+            ..Default::default()
+        };
+        let shutdown = CancellationToken::new();
+        tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move {
+                sleep(Duration::from_secs(1)).await;
+                shutdown.cancel();
+            }
+        });
+
+        let start = Instant::now();
+        let result = retry_future(
+            &config,
+            &shutdown,
+            || async { Err::<(), _>(anyhow!("failed")) },
+            |_| FailureKind::Transient,
+        )
+        .await;
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<ClientError>(),
+            Some(ClientError::Cancelled)
+        ));
+        assert_eq!(err.root_cause().to_string(), "failed");
+        assert_eq!(start.elapsed(), Duration::from_secs(1));
+    }
 }
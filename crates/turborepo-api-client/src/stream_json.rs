@@ -0,0 +1,85 @@
+use std::io::{self, Read};
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+
+use crate::ClientError;
+
+/// Responses at least this large are decoded as they arrive rather than
+/// buffered first.
+pub(crate) const STREAMING_THRESHOLD_BYTES: u64 = 1024 * 1024;
+
+/// How many chunks can be waiting for the parser before reading from the
+/// network pauses.
+const MAX_PENDING_CHUNKS: usize = 4;
+
+/// Deserializes a JSON response body while it is being downloaded, so peak
+/// memory is the parsed value plus a few chunks instead of the whole body as
+/// well. Parsing happens on a blocking thread fed chunks over a channel.
+pub(crate) async fn read_json_streaming<T: DeserializeOwned + Send + 'static>(
+    mut response: reqwest::Response,
+    max_response_bytes: Option<usize>,
+) -> Result<T> {
+    let (tx, rx) = mpsc::channel(MAX_PENDING_CHUNKS);
+    let parser = tokio::task::spawn_blocking(move || {
+        serde_json::from_reader::<_, T>(ChannelReader {
+            rx,
+            chunk: Vec::new(),
+            position: 0,
+        })
+    });
+
+    let mut received = 0;
+    let download: Result<()> = async {
+        while let Some(chunk) = response.chunk().await? {
+            received += chunk.len();
+            if let Some(limit) = max_response_bytes {
+                if received > limit {
+                    return Err(ClientError::ResponseTooLarge { limit }.into());
+                }
+            }
+            if tx.send(chunk.to_vec()).await.is_err() {
+                // The parser has already failed, its error is returned below
+                break;
+            }
+        }
+        Ok(())
+    }
+    .await;
+    // Closing the channel ends the parser's input
+    drop(tx);
+
+    let parsed = parser.await?;
+    // A failed download leaves the parser with truncated input, so its error
+    // is the less useful one
+    download?;
+    Ok(parsed?)
+}
+
+/// Presents chunks received over a channel as a blocking reader.
+struct ChannelReader {
+    rx: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+
+        let remaining = &self.chunk[self.position..];
+        let n = remaining.len().min(buf.len());
+        buf[..n].copy_from_slice(&remaining[..n]);
+        self.position += n;
+        Ok(n)
+    }
+}
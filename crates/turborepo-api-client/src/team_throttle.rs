@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Caps how many requests can be in flight for the same team at once, so a
+/// pipeline fanning out many requests doesn't trip per-team rate limits.
+pub(crate) struct TeamThrottle {
+    max_concurrent_requests: usize,
+    semaphores: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+impl TeamThrottle {
+    /// A cap of 0 would never let a request through, so it is raised to 1.
+    pub(crate) fn new(max_concurrent_requests: usize) -> Self {
+        Self {
+            max_concurrent_requests: max_concurrent_requests.max(1),
+            semaphores: Mutex::default(),
+        }
+    }
+
+    /// Waits until a request for `team_id` may be sent. The returned permit
+    /// should be held until the response has been read.
+    pub(crate) async fn acquire(&self, team_id: &str) -> OwnedSemaphorePermit {
+        let semaphore = {
+            let mut semaphores = self.semaphores.lock().expect("team throttle lock poisoned");
+            semaphores
+                .entry(team_id.to_string())
+                .or_insert_with(|| Arc::new(Semaphore::new(self.max_concurrent_requests)))
+                .clone()
+        };
+
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("team throttle semaphore is never closed")
+    }
+}
+
+impl Default for TeamThrottle {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::TeamThrottle;
+
+    async fn max_in_flight(throttle: Arc<TeamThrottle>, teams: &[&'static str]) -> usize {
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max_in_flight = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = teams
+            .iter()
+            .map(|&team| {
+                let throttle = throttle.clone();
+                let in_flight = in_flight.clone();
+                let max_in_flight = max_in_flight.clone();
+                tokio::spawn(async move {
+                    let _permit = throttle.acquire(team).await;
+                    let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_in_flight.fetch_max(current, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        max_in_flight.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_caps_requests_per_team() {
+        let throttle = Arc::new(TeamThrottle::new(2));
+        assert_eq!(max_in_flight(throttle, &["team_a"; 6]).await, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_zero_cap_allows_one_request() {
+        let throttle = Arc::new(TeamThrottle::new(0));
+        assert_eq!(max_in_flight(throttle, &["team_a"; 3]).await, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_teams_are_throttled_separately() {
+        let throttle = Arc::new(TeamThrottle::new(1));
+        assert_eq!(
+            max_in_flight(throttle, &["team_a", "team_b", "team_c"]).await,
+            3
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// How long a single request took, reported to the callback set with
+/// `APIClient::with_timing_callback`.
+///
+/// reqwest doesn't expose the individual connection phases, so DNS
+/// resolution, connecting and the TLS handshake are all part of
+/// `time_to_headers`.
+#[derive(Debug, Clone)]
+pub struct RequestTiming {
+    pub method: reqwest::Method,
+    /// Only the path is included, since query parameters can contain tokens
+    pub path: String,
+    /// `None` if no response was received
+    pub status: Option<u16>,
+    /// When the request was sent
+    pub started_at: Instant,
+    /// When the response headers were received, or the request failed
+    pub finished_at: Instant,
+}
+
+impl RequestTiming {
+    /// The time from sending the request until the response headers were
+    /// received, which includes connecting and the server's processing time.
+    pub fn time_to_headers(&self) -> Duration {
+        self.finished_at.duration_since(self.started_at)
+    }
+}
+
+pub(crate) type TimingCallback = Arc<dyn Fn(&RequestTiming) + Send + Sync>;
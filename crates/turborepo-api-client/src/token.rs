@@ -0,0 +1,51 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::{sync::Mutex, time::Instant};
+
+/// A bearer token, and when it stops being valid.
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub value: String,
+    /// `None` if the token never expires.
+    pub expires_at: Option<Instant>,
+}
+
+/// Supplies the bearer token for requests, e.g. by refreshing a short-lived
+/// OAuth token.
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    async fn token(&self) -> Result<Token>;
+}
+
+/// Reuses the provider's token until it expires. The lock is held while
+/// refreshing so that concurrent requests only refresh once.
+pub(crate) struct CachedTokenProvider {
+    provider: Box<dyn TokenProvider>,
+    cached: Mutex<Option<Token>>,
+}
+
+impl CachedTokenProvider {
+    pub(crate) fn new(provider: impl TokenProvider + 'static) -> Self {
+        Self {
+            provider: Box::new(provider),
+            cached: Mutex::default(),
+        }
+    }
+
+    pub(crate) async fn token(&self) -> Result<String> {
+        let mut cached = self.cached.lock().await;
+        if let Some(token) = &*cached {
+            if token
+                .expires_at
+                .map_or(true, |expires_at| Instant::now() < expires_at)
+            {
+                return Ok(token.value.clone());
+            }
+        }
+
+        let token = self.provider.token().await?;
+        let value = token.value.clone();
+        *cached = Some(token);
+        Ok(value)
+    }
+}
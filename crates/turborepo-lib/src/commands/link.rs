@@ -60,7 +60,7 @@ pub(crate) async fn verify_caching_enabled<'a>(
         SelectedTeam::User => None,
     });
     let response = api_client
-        .get_caching_status(token, team_id, team_slug)
+        .get_caching_status(Some(token), team_id, team_slug)
         .await?;
     match response.status {
         CachingStatus::Disabled => {
@@ -80,7 +80,7 @@ pub(crate) async fn verify_caching_enabled<'a>(
                     }
                     None => {
                         let team = api_client
-                            .get_team(token, team_id)
+                            .get_team(Some(token), team_id)
                             .await?
                             .ok_or_else(|| anyhow!("unable to find team {}", team_id))?;
                         let url =
@@ -94,8 +94,9 @@ pub(crate) async fn verify_caching_enabled<'a>(
 
             Ok(())
         }
-        CachingStatus::OverLimit => Err(anyhow!("usage limit")),
-        CachingStatus::Paused => Err(anyhow!("spending paused")),
+        CachingStatus::OverLimit | CachingStatus::Paused => {
+            Err(anyhow!("{}", response.user_message()))
+        }
         CachingStatus::Enabled => Ok(()),
     }
 }
@@ -128,12 +129,12 @@ pub async fn link(base: &mut CommandBase, modify_gitignore: bool) -> Result<()>
     })?;
 
     let teams_response = api_client
-        .get_teams(token)
+        .get_teams(Some(token))
         .await
         .context("could not get team information")?;
 
     let user_response = api_client
-        .get_user(token)
+        .get_user(Some(token))
         .await
         .context("could not get user information")?;
 
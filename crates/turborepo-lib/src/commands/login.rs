@@ -50,7 +50,7 @@ pub async fn sso_login(base: &mut CommandBase, sso_team: &str) -> Result<()> {
 
     let api_client = base.api_client()?;
     let verified_user = api_client.verify_sso_token(token, &token_name).await?;
-    let user_response = api_client.get_user(&verified_user.token).await?;
+    let user_response = api_client.get_user(Some(&verified_user.token)).await?;
 
     base.user_config_mut()?
         .set_token(Some(verified_user.token.clone()))?;
@@ -137,7 +137,7 @@ pub async fn login(base: &mut CommandBase) -> Result<()> {
     base.user_config_mut()?.set_token(Some(token.to_string()))?;
 
     let client = base.api_client()?;
-    let user_response = client.get_user(token.as_str()).await?;
+    let user_response = client.get_user(Some(token.as_str())).await?;
 
     let ui = &base.ui;
 
@@ -57,6 +57,7 @@ pub async fn start_test_server(port: u16) -> Result<()> {
             get(|| async {
                 Json(CachingStatusResponse {
                     status: CachingStatus::Enabled,
+                    message: None,
                 })
             }),
         )